@@ -1,250 +1,1013 @@
-use std::io::Write;
+use std::io::IsTerminal;
 
 use chewwy::{
     cfg::{self, Cfg, StructMerge},
+    clean::{self, CleanOptions, CleanSummary},
+    compress::{self, CompressOptions, CompressSummary},
+    doctor,
+    file_archiver::FileArchiver,
+    manage::{self, ManageOptions, ManageSummary, Selection},
     prelude::*,
     utils,
 };
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use serde::Serialize;
 
 #[derive(Parser)]
 struct Args {
+    /// Config layered over the root, global and default configs. Repeat
+    /// to layer several, later files take precedence
     #[arg(short, long, value_name = "PATH")]
-    config_file: Option<String>,
+    config_file: Vec<String>,
+    /// Print the result and errors as JSON
+    #[arg(long)]
+    json: bool,
+    /// Log what is being done to stderr, repeat for more detail
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Print only the requested output and errors, overrides
+    /// `options.quiet`
+    #[arg(short, long)]
+    quiet: bool,
+    /// When to color errors and the file picker, overrides `options.color`
+    #[arg(long, value_name = "WHEN")]
+    color: Option<ColorArg>,
+    /// Decompress stdin to stdout with the `decompress-pipe` commands of
+    /// this format instead of running a command
+    #[arg(long, value_name = "FORMAT")]
+    pipe: Option<String>,
+    /// Print the JSON Schema of the config for editors instead of running
+    /// a command
+    #[arg(long, conflicts_with = "pipe")]
+    config_schema: bool,
     #[command(subcommand)]
     command: Option<Command>,
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<ColorArg> for cfg::ColorChoice {
+    fn from(color: ColorArg) -> Self {
+        match color {
+            ColorArg::Auto => cfg::ColorChoice::Auto,
+            ColorArg::Always => cfg::ColorChoice::Always,
+            ColorArg::Never => cfg::ColorChoice::Never,
+        }
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum SortArg {
+    Name,
+    ModifiedDesc,
+    SizeDesc,
+}
+
+impl From<SortArg> for cfg::SortKey {
+    fn from(sort: SortArg) -> Self {
+        match sort {
+            SortArg::Name => cfg::SortKey::Name,
+            SortArg::ModifiedDesc => cfg::SortKey::ModifiedDesc,
+            SortArg::SizeDesc => cfg::SortKey::SizeDesc,
+        }
+    }
+}
+
+// parsed once, boxing the args isn't worth it
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Command {
     /// Manage a file
-    Manage {
-        #[arg(value_name = "PATH")]
-        file: Option<PathBuf>,
+    Manage(ManageArgs),
+    /// Compress a directory into an archive next to it
+    Compress(CompressArgs),
+    /// Remove the output directories whose archive is gone
+    Clean(CleanArgs),
+    /// Check that the merged config has every required field
+    Validate,
+    /// Print the config merged from every layer as TOML
+    Config,
+    /// List the entries of an archive without extracting it
+    List { file: PathBuf },
+    /// Check the chewwy root, config, directories and commands
+    Doctor,
+    /// Print the chewwy root found from the current directory, fails if
+    /// there is none
+    Root {
+        /// Also print the path of the root config
+        #[arg(long)]
+        config: bool,
     },
+    /// Make the current directory a chewwy root
+    Init,
+    /// Print a shell completion script
+    #[command(hide = true)]
+    Completions { shell: clap_complete::Shell },
+}
+
+#[derive(clap::Args)]
+struct ManageArgs {
+    /// Files to manage, one is searched for if none is given.
+    /// Glob patterns like `*.zip` are expanded
+    #[arg(value_name = "PATH")]
+    files: Vec<PathBuf>,
+    /// Read the files to manage from stdin, one path per line, like
+    /// `find . -name '*.zip' | chewer manage --stdin`
+    #[arg(long, conflicts_with = "files")]
+    stdin: bool,
+    /// Only test the integrity of the files with the `verify` commands of
+    /// their formats, extracting and moving nothing
+    #[arg(
+        long,
+        conflicts_with_all = ["dry_run", "stdout", "to", "move_to", "print_output_dir"]
+    )]
+    verify_only: bool,
+    /// After managing the files already there, keep managing the files
+    /// appearing in the search directory until Ctrl-C. A file is managed
+    /// once its size stops changing
+    #[cfg(feature = "watch")]
+    #[arg(
+        long,
+        conflicts_with_all = ["files", "stdin", "verify_only", "stdout", "to", "print_output_dir"]
+    )]
+    watch: bool,
+    /// Continue with the next file when one fails
+    #[arg(long)]
+    keep_going: bool,
+    /// Manage up to this many files at a time
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+    /// Move the compressed file to this directory when done
+    #[arg(long, value_name = "DIR")]
+    move_to: Option<PathBuf>,
+    /// Only print what would be done
+    #[arg(long)]
+    dry_run: bool,
+    /// Write the decompressed content to stdout instead of a directory
+    #[arg(long, conflicts_with = "to")]
+    stdout: bool,
+    /// Decompress directly into this directory, created if needed, instead
+    /// of a directory in the output directory
+    #[arg(long, value_name = "DIR")]
+    to: Option<PathBuf>,
+    /// Override the configured search directory
+    #[arg(long, value_name = "DIR")]
+    search_dir: Option<PathBuf>,
+    /// Override the configured output directory
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+    /// Override the configured archive directory
+    #[arg(long, value_name = "DIR")]
+    archive_dir: Option<PathBuf>,
+    /// Run this after each managed file instead of the configured
+    /// `post-decompress`, split on whitespace. `{OUTPUT_DIR}` and
+    /// `{ARCHIVE}` are substituted
+    #[arg(long, value_name = "CMD")]
+    after_cmd: Option<String>,
+    /// Password of an encrypted archive
+    #[arg(long, value_name = "PASSWORD", conflicts_with = "password_file")]
+    password: Option<String>,
+    /// Read the password of an encrypted archive from the first line of
+    /// this file
+    #[arg(long, value_name = "FILE")]
+    password_file: Option<PathBuf>,
+    /// Don't check for free space on the output filesystem first
+    #[arg(long)]
+    no_space_check: bool,
+    /// Only print the final output directory on stdout, other messages
+    /// go to stderr
+    #[arg(long, conflicts_with = "stdout")]
+    print_output_dir: bool,
+    /// List symlinks to directories when searching, overrides
+    /// `commands.manage.follow-symlinks`
+    #[arg(long)]
+    follow_symlinks: bool,
+    /// Only list searched files modified within this long, like `24h` or
+    /// `7d`
+    #[arg(
+        long,
+        visible_alias = "newer-than",
+        value_name = "DURATION",
+        value_parser = utils::parse_duration
+    )]
+    since: Option<std::time::Duration>,
+    /// Decompress with the format of this name instead of the one matching
+    /// the file
+    #[arg(long, value_name = "NAME")]
+    format: Option<String>,
+    /// Order of the searched files, overrides `commands.manage.search-sort`
+    #[arg(long, value_name = "KEY")]
+    sort: Option<SortArg>,
+    /// Also search this many levels of subdirectories, overrides
+    /// `commands.manage.search-depth`
+    #[arg(long, value_name = "N")]
+    depth: Option<usize>,
+    /// Fail instead of listing more searched files than this, overrides
+    /// `commands.manage.search-max-entries`
+    #[arg(long, value_name = "N")]
+    max_entries: Option<usize>,
+    /// Never prompt, only choose a searched file if it's the only one.
+    /// Implied when stdin is not a terminal
+    #[arg(long)]
+    no_interactive: bool,
+}
+
+#[derive(clap::Args)]
+struct CompressArgs {
+    #[arg(value_name = "DIR")]
+    dir: PathBuf,
+    /// Name of the format to compress with instead of the configured one
+    #[arg(long)]
+    format: Option<String>,
+    /// Delete the directory once compressed
+    #[arg(long, conflicts_with = "move_source_to")]
+    delete_source: bool,
+    /// Move the directory here once compressed
+    #[arg(long, value_name = "DIR")]
+    move_source_to: Option<PathBuf>,
+}
+
+#[derive(clap::Args)]
+struct CleanArgs {
+    /// Also remove output directories not modified for this long, like
+    /// `30d`, even if their archive is still there
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration)]
+    older_than: Option<std::time::Duration>,
+    /// Only print what would be removed
+    #[arg(long)]
+    dry_run: bool,
+    /// Remove without asking
+    #[arg(short, long)]
+    yes: bool,
 }
 
 #[derive(Debug, Error)]
 #[error("app error")]
 struct AppError;
 
-fn main() -> StackResult<(), AppError> {
-    let args = Args::parse();
+/// Exit code for the failure class of the report:
+/// - 1 anything else
+/// - 2 config not found, invalid or incomplete
+/// - 3 no format available for the file
+/// - 4 a decompress command is missing or failed
+/// - 5 cancelled by the user
+/// - 6 the command needs a chewwy root and there is none
+/// - 130 interrupted by Ctrl-C while a command ran
+fn exit_code<C>(report: &error_stack::Report<C>) -> u8 {
+    use chewwy::file_archiver::DecompressError;
+    if report.contains::<manage::Cancelled>() {
+        return 5;
+    }
+    if report.contains::<chewwy::NoChewwyRoot>() {
+        return 6;
+    }
+    if let Some(e) = report.downcast_ref::<DecompressError>() {
+        return match e {
+            // 128 + SIGINT like shells
+            DecompressError::Interrupted { .. } => 130,
+            DecompressError::NoFormatAvailable { .. }
+            | DecompressError::FindFormat(_) => 3,
+            _ => 4,
+        };
+    }
+    if report.contains::<cfg::LoadCfgError>()
+        || report.contains::<cfg::ResolveExtendsError>()
+        || report.contains::<cfg::MissingField>()
+        || report.contains::<CommandValidateError>()
+    {
+        return 2;
+    }
+    1
+}
 
-    let current_dir = env::current_dir().change_context(AppError)?;
-    let chewwy_root =
-        chewwy::search_chewwy_root(current_dir).change_context(AppError)?;
+/// What a command reports on success
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case", tag = "command")]
+enum Output {
+    Manage(ManageSummary),
+    ManageBatch {
+        managed: Vec<ManageSummary>,
+    },
+    /// `manage --print-output-dir`
+    ManagedDirs {
+        output_dirs: Vec<PathBuf>,
+    },
+    Compress(CompressSummary),
+    Clean(CleanSummary),
+    Validate,
+    Config {
+        config: Box<Cfg>,
+    },
+    List {
+        format: String,
+    },
+    Pipe {
+        format: String,
+    },
+    Doctor {
+        checks: Vec<doctor::Check>,
+    },
+    Root {
+        root: Option<PathBuf>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        config: Option<PathBuf>,
+    },
+    Init {
+        root: PathBuf,
+    },
+    /// `manage --verify-only`
+    Verify {
+        verified: Vec<manage::VerifySummary>,
+    },
+    /// No command given and no usable `options.default-command`
+    Help,
+}
 
-    let arg_cfg = match args.config_file {
-        Some(c) => Some(cfg::load_cfg(c).change_context(AppError)?),
-        None => None,
-    };
-    let chewwy_root_cfg = match &chewwy_root {
-        Some(chewwy_root) => {
-            match cfg::load_cfg(cfg::root_cfg_path(chewwy_root)) {
-                Ok(c) => Some(c),
-                Err(cfg::LoadCfgError::Io(e))
-                    if e.kind() == io::ErrorKind::NotFound =>
-                {
-                    None
+impl Output {
+    /// Exit code of a command reporting a failure despite succeeding to run
+    fn failure_code(&self) -> Option<u8> {
+        match self {
+            Output::Doctor { checks } => checks
+                .iter()
+                .any(|check| check.status == doctor::CheckStatus::Fail)
+                .then_some(1),
+            Output::Root { root, .. } => root.is_none().then_some(1),
+            Output::Verify { verified } => verified
+                .iter()
+                .any(|summary| summary.status != manage::VerifyStatus::Ok)
+                .then_some(1),
+            // the code of clap's usage errors
+            Output::Help => Some(2),
+            _ => None,
+        }
+    }
+
+    fn print_human(&self, messages: utils::Messages) {
+        match self {
+            Output::Manage(_) => {}
+            Output::ManageBatch { managed } => {
+                messages.say(format!("Managed {} files", managed.len()))
+            }
+            Output::ManagedDirs { output_dirs } => {
+                for output_dir in output_dirs {
+                    println!("{}", output_dir.display());
+                }
+            }
+            Output::Compress(summary) => {
+                messages.say(format!("Created `{}`", summary.archive.display()))
+            }
+            Output::Clean(summary) => {
+                let verb = if summary.dry_run {
+                    "Would remove"
+                } else {
+                    "Removed"
+                };
+                for dir in &summary.removed {
+                    messages.say(format!("{verb} `{}`", dir.display()));
+                }
+                if summary.removed.is_empty() {
+                    messages.say("Nothing to clean");
                 }
-                Err(e) => return Err(e).change_context(AppError),
+            }
+            Output::Validate => messages.say("Config is valid"),
+            Output::List { .. } | Output::Pipe { .. } => {}
+            Output::Doctor { checks } => {
+                for check in checks {
+                    println!("{check}");
+                }
+            }
+            Output::Config { config } => {
+                print!("{}", toml::to_string(config).expect("serialize config"))
+            }
+            Output::Root { root, config } => {
+                for path in [root, config].into_iter().flatten() {
+                    println!("{}", path.display());
+                }
+            }
+            Output::Verify { verified } => {
+                for summary in verified {
+                    println!("{summary}");
+                }
+            }
+            Output::Init { root } => messages
+                .say(format!("Initialized chewwy root `{}`", root.display())),
+            Output::Help => {
+                Args::command().print_help().expect("print help");
             }
         }
-        None => None,
-    };
-    let default_cfg = Cfg::default();
-    let cfg = match (chewwy_root_cfg, arg_cfg) {
-        (None, None) => default_cfg,
-        (None, Some(mut a)) => {
-            a.struct_merge(&default_cfg);
-            a
-        }
-        (Some(mut r), None) => {
-            r.struct_merge(&default_cfg);
-            r
-        }
-        (Some(r), Some(mut a)) => {
-            a.struct_merge(&r);
-            a.struct_merge(&default_cfg);
-            a
+    }
+}
+
+/// Logs every enabled record to stderr
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
         }
+    }
+
+    fn flush(&self) {}
+}
+
+fn init_logger(verbose: u8, quiet: bool) {
+    let level = match verbose {
+        0 if quiet => log::LevelFilter::Error,
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
     };
+    log::set_logger(&StderrLogger).expect("logger is only set once");
+    log::set_max_level(level);
+}
+
+fn main() -> process::ExitCode {
+    let args = Args::parse();
+    init_logger(args.verbose, args.quiet);
+    chewwy::interrupt::install();
+    if let Some(Command::Completions { shell }) = args.command {
+        clap_complete::generate(
+            shell,
+            &mut Args::command(),
+            env!("CARGO_BIN_NAME"),
+            &mut io::stdout(),
+        );
+        return process::ExitCode::SUCCESS;
+    }
+    if args.config_schema {
+        let schema = chewwy::schema::cfg_schema();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&schema).expect("serialize schema")
+        );
+        return process::ExitCode::SUCCESS;
+    }
+    let json = args.json;
+    let messages = utils::Messages::new(args.quiet);
+    match run(args) {
+        Ok(output) => {
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string(&output).expect("serialize output")
+                );
+            } else {
+                output.print_human(messages);
+            }
+            if let Some(code) = output.failure_code() {
+                return process::ExitCode::from(code);
+            }
+            process::ExitCode::SUCCESS
+        }
+        Err(report) => {
+            if json {
+                println!("{}", report_to_json(&report));
+            } else if report.contains::<chewwy::NoChewwyRoot>() {
+                eprintln!("Error: {NO_CHEWWY_ROOT_HINT}");
+            } else {
+                eprintln!("Error: {report:?}");
+            }
+            process::ExitCode::from(exit_code(&report))
+        }
+    }
+}
+
+const NO_CHEWWY_ROOT_HINT: &str = "no chewwy root found, run `chewer init` \
+     to make the current directory one or cd into a project with a `.chewwy` \
+     directory";
 
-    match args.command {
-        Some(command) => match command {
-            Command::Manage { file } => {
-                command_manage(&cfg, &chewwy_root, file)
-                    .change_context(AppError)?;
+/// The innermost error of the report
+fn root_cause<C>(report: &error_stack::Report<C>) -> String {
+    use error_stack::FrameKind;
+    report
+        .frames()
+        .filter_map(|frame| match frame.kind() {
+            FrameKind::Context(context) => Some(context.to_string()),
+            FrameKind::Attachment(_) => None,
+        })
+        .last()
+        .unwrap_or_default()
+}
+
+fn report_to_json<C>(report: &error_stack::Report<C>) -> serde_json::Value {
+    use error_stack::{AttachmentKind, FrameKind};
+    let mut errors = vec![];
+    let mut messages = vec![];
+    for frame in report.frames() {
+        match frame.kind() {
+            FrameKind::Context(context) => errors.push(context.to_string()),
+            FrameKind::Attachment(AttachmentKind::Printable(printable)) => {
+                messages.push(printable.to_string())
             }
+            FrameKind::Attachment(_) => {}
+        }
+    }
+    serde_json::json!({ "errors": errors, "messages": messages })
+}
+
+fn run(args: Args) -> StackResult<Output, AppError> {
+    let json = args.json;
+    let current_dir = env::current_dir().change_context(AppError)?;
+    let chewwy_root = chewwy::search_chewwy_root_bounded(
+        &current_dir,
+        &chewwy::SearchBound::home(),
+    )
+    .change_context(AppError)?;
+    match &chewwy_root {
+        Some(chewwy_root) => {
+            log::info!("found chewwy root `{}`", chewwy_root.display())
+        }
+        None => log::info!("no chewwy root found"),
+    }
+    let cfg = load_merged_cfg(&args.config_file, chewwy_root.as_deref())
+        .and_then(|mut cfg| {
+            cfg.resolve_extends().change_context(AppError)?;
+            Ok(cfg)
+        });
+    if let Some(format) = args.pipe {
+        if args.command.is_some() {
+            return Err(AppError)
+                .attach_printable("`--pipe` can't be used with a command");
+        }
+        let cfg = cfg?;
+        let options_cfg = cfg.options.c();
+        let format = FileArchiver::new(cfg.formats.c())
+            .chewwy_root(chewwy_root.as_deref())
+            .redact(options_cfg.redact.c().clone())
+            .decompress_pipe(&format)
+            .change_context(AppError)
+            .attach_printable("cannot decompress stdin")?;
+        return Ok(Output::Pipe { format });
+    }
+    let command = args.command.or_else(|| {
+        let options = cfg.as_ref().ok()?.options.get()?;
+        default_command(options.default_command.get().map(String::as_str))
+    });
+    let Some(command) = command else {
+        return Ok(Output::Help);
+    };
+    if let Command::Init = command {
+        let root = chewwy::init_chewwy_root(&current_dir)
+            .change_context(AppError)
+            .attach_printable_lazy(|| {
+                format!("cannot make `{}` a chewwy root", current_dir.display())
+            })?;
+        return Ok(Output::Init { root });
+    }
+    if let Command::Root { config } = command {
+        let config = chewwy_root
+            .as_ref()
+            .filter(|_| config)
+            .map(cfg::root_cfg_path);
+        return Ok(Output::Root {
+            root: chewwy_root,
+            config,
+        });
+    }
+    if let Command::Doctor = command {
+        let checks = doctor::diagnose(
+            chewwy_root.as_deref(),
+            cfg.as_ref().map_err(root_cause),
+            env::var_os("PATH").as_deref(),
+        );
+        return Ok(Output::Doctor { checks });
+    }
+    let mut cfg = cfg?;
+    if let Some(options) = &mut cfg.options.0 {
+        if args.quiet {
+            options.quiet = cfg::Configure(Some(true));
+        }
+        if let Some(color) = args.color {
+            options.color = cfg::Configure(Some(color.into()));
+        }
+    }
+
+    let options_cfg =
+        cfg.options.c_or_err("options").change_context(AppError)?;
+    let color = options_cfg
+        .color
+        .c_or_err("options.color")
+        .change_context(AppError)?;
+    error_stack::Report::set_color_mode(color_mode(*color));
+
+    let output = match command {
+        Command::Manage(manage_args) => {
+            command_manage(&cfg, chewwy_root.as_deref(), manage_args, json)
+                .change_context(AppError)?
+        }
+        Command::Compress(compress_args) => {
+            let source_dir_action = if compress_args.delete_source {
+                Some(cfg::SourceDirAction::Delete)
+            } else {
+                compress_args
+                    .move_source_to
+                    .map(path::absolute)
+                    .transpose()
+                    .change_context(AppError)?
+                    .map(|path| cfg::SourceDirAction::MoveToDir { path })
+            };
+            let options = CompressOptions {
+                dir: compress_args.dir,
+                format: compress_args.format,
+                source_dir_action,
+            };
+            let summary = compress::run(&cfg, chewwy_root.as_deref(), options)
+                .change_context(AppError)?;
+            Output::Compress(summary)
+        }
+        Command::Clean(clean_args) => {
+            let options = CleanOptions {
+                older_than: clean_args.older_than,
+                dry_run: clean_args.dry_run,
+                yes: clean_args.yes,
+                ..Default::default()
+            };
+            let summary = clean::run(&cfg, chewwy_root.as_deref(), options)
+                .change_context(AppError)?;
+            Output::Clean(summary)
+        }
+        Command::Validate => {
+            command_validate(&cfg, chewwy_root.as_deref())
+                .change_context(AppError)?;
+            Output::Validate
+        }
+        Command::Config => Output::Config {
+            config: Box::new(cfg),
         },
-        None => {
-            todo!()
+        Command::List { file } => {
+            let format = FileArchiver::new(
+                cfg.formats.c_or_err("formats").change_context(AppError)?,
+            )
+            .chewwy_root(chewwy_root.as_deref())
+            .case_sensitive_extensions(
+                *options_cfg.case_sensitive_extensions.or(&false),
+            )
+            .redact(options_cfg.redact.or(&vec![]).clone())
+            .list(&file)
+            .change_context(AppError)
+            .attach_printable_lazy(|| {
+                format!("cannot list `{}`", file.display())
+            })?;
+            Output::List { format }
+        }
+        Command::Completions { .. } => {
+            unreachable!("completions are generated before loading config")
+        }
+        Command::Doctor => {
+            unreachable!("doctor runs before loading config")
+        }
+        Command::Root { .. } => {
+            unreachable!("root is printed before its config is needed")
+        }
+        Command::Init => {
+            unreachable!("init runs before loading config")
+        }
+    };
+
+    Ok(output)
+}
+
+/// Merge every config layer by precedence: args from the last > root >
+/// global > default
+fn load_merged_cfg(
+    config_files: &[String],
+    chewwy_root: Option<&Path>,
+) -> StackResult<Cfg, AppError> {
+    // highest precedence first
+    let mut layers = vec![];
+    for config_file in config_files.iter().rev() {
+        let arg_cfg = cfg::load_cfg(config_file).change_context(AppError)?;
+        log::info!("loaded config `{config_file}`");
+        layers.push(("arg", arg_cfg));
+    }
+    if let Some(chewwy_root) = chewwy_root {
+        let root_cfg_path = cfg::root_cfg_path(chewwy_root);
+        if let Some(root_cfg) = load_optional_cfg(&root_cfg_path)? {
+            layers.push(("root", root_cfg));
         }
     }
+    if let Some(global_cfg_path) = cfg::global_cfg_path() {
+        if let Some(global_cfg) = load_optional_cfg(&global_cfg_path)? {
+            layers.push(("global", global_cfg));
+        }
+    }
+    layers.push(("default", Cfg::default()));
+    log::debug!(
+        "merging config layers: {}",
+        layers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(" > ")
+    );
+
+    let mut layers = layers.into_iter().map(|(_, layer)| layer);
+    let mut cfg = layers.next().expect("default layer");
+    for layer in layers {
+        cfg.struct_merge(&layer);
+    }
+    Ok(cfg)
+}
+
+fn load_optional_cfg(path: &Path) -> StackResult<Option<Cfg>, AppError> {
+    let cfg = cfg::load_cfg_if_exists(path).change_context(AppError)?;
+    match &cfg {
+        Some(_) => log::info!("loaded config `{}`", path.display()),
+        None => log::debug!("no config at `{}`", path.display()),
+    }
+    Ok(cfg)
+}
 
-    Ok(())
+fn color_mode(color: cfg::ColorChoice) -> error_stack::fmt::ColorMode {
+    use error_stack::fmt::ColorMode;
+    if color.enabled(io::stderr().is_terminal()) {
+        ColorMode::Color
+    } else {
+        ColorMode::None
+    }
+}
+
+/// Parse `options.default-command`, `None` when unset or it doesn't parse,
+/// like when it misses an argument
+fn default_command(default_command: Option<&str>) -> Option<Command> {
+    #[derive(Parser)]
+    struct DefaultCommand {
+        #[command(subcommand)]
+        command: Command,
+    }
+    let default_command = default_command?;
+    let argv = std::iter::once(env!("CARGO_BIN_NAME"))
+        .chain(default_command.split_whitespace());
+    match DefaultCommand::try_parse_from(argv) {
+        Ok(DefaultCommand {
+            command: Command::Completions { .. },
+        }) => {
+            log::warn!("`options.default-command` can't be completions");
+            None
+        }
+        Ok(parsed) => Some(parsed.command),
+        Err(e) => {
+            log::warn!(
+                "can't run `options.default-command` `{default_command}`: {}",
+                e.kind()
+            );
+            None
+        }
+    }
 }
 
 #[derive(Debug, Error)]
 #[error("command manage error")]
 struct CommandManageError;
 
-fn command_manage<R: AsRef<Path>, F: AsRef<Path>>(
+fn read_password_file(
+    password_file: &Path,
+) -> StackResult<String, CommandManageError> {
+    let content = fs::read_to_string(password_file)
+        .change_context(CommandManageError)
+        .attach_printable_lazy(|| {
+            format!("can't read password file `{}`", password_file.display())
+        })?;
+    Ok(content.lines().next().unwrap_or_default().to_string())
+}
+
+fn command_manage(
     cfg: &Cfg,
-    chewwy_root: &Option<R>,
-    file: Option<F>,
-) -> StackResult<(), CommandManageError> {
-    let Some(chewwy_root) = chewwy_root else {
-        return Err(CommandManageError)
-            .attach_printable("Chewwy root not found for this command");
+    chewwy_root: Option<&Path>,
+    args: ManageArgs,
+    json: bool,
+) -> StackResult<Output, CommandManageError> {
+    let absolute = |path: Option<PathBuf>| {
+        path.map(path::absolute)
+            .transpose()
+            .change_context(CommandManageError)
+    };
+    let options = ManageOptions {
+        file: None,
+        dry_run: args.dry_run,
+        directories: cfg::Directories {
+            search: cfg::Configure(absolute(args.search_dir)?.map(Some)),
+            output: cfg::Configure(absolute(args.output_dir)?.map(Some)),
+            archive: cfg::Configure(absolute(args.archive_dir)?.map(Some)),
+        },
+        move_to: absolute(args.move_to)?,
+        selection: if args.no_interactive || !io::stdin().is_terminal() {
+            Selection::Single
+        } else {
+            Selection::Prompt
+        },
+        output_file_action: args
+            .stdout
+            .then_some(cfg::OutputFileAction::ToStdout),
+        buffer_output: false,
+        to: absolute(args.to)?,
+        after_cmd: args.after_cmd.map(|after_cmd| {
+            after_cmd.split_whitespace().map(String::from).collect()
+        }),
+        password: match args.password_file {
+            Some(password_file) => Some(read_password_file(&password_file)?),
+            None => args.password,
+        },
+        skip_space_check: args.no_space_check,
+        // stdout is only for the printed output
+        messages_to_stderr: args.print_output_dir || json,
+        follow_symlinks: args.follow_symlinks,
+        since: args.since,
+        sort: args.sort.map(Into::into),
+        search_depth: args.depth,
+        max_entries: args.max_entries,
+        format: args.format,
+        runner: None,
+    };
+    let managed_dirs = |managed: Vec<ManageSummary>| {
+        let output_dirs = managed
+            .into_iter()
+            .filter_map(|summary| summary.output_dir)
+            .map(path::absolute)
+            .collect::<Result<_, _>>()
+            .change_context(CommandManageError)?;
+        Ok(Output::ManagedDirs { output_dirs })
     };
-    let chewwy_root = chewwy_root.as_ref();
-    let manage_cfg = cfg.commands.c().manage.c();
-    let directories_cfg = manage_cfg.directories.c().to_absolute(chewwy_root);
-    let formats_cfg = cfg.formats.c();
-    let mut file = file.map(|f| f.as_ref().to_path_buf());
-
-    if file.is_none() {
-        if !manage_cfg.search_file.c() {
-            return Err(CommandManageError).attach_printable(
-                "File is not provided. Or try to use search-file feature",
-            );
-        }
-        let Some(search_dir) = directories_cfg.search.c() else {
-            return Err(CommandManageError).attach_printable(
-                "File is not provided and search directory is not configured.",
-            );
-        };
-        let search_dir_canon = match search_dir.canonicalize() {
-            Ok(o) => o,
-            Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                return Err(CommandManageError).attach_printable_lazy(|| {
-                    format!(
-                        "Configured search dir `{}` is not found",
-                        search_dir.display()
-                    )
-                })
-            }
-            Err(e) => {
-                return Err(e)
-                    .change_context(CommandManageError)
-                    .attach_printable("search dir")
-            }
-        };
 
-        let mut items = vec![];
-        for entry in fs::read_dir(search_dir_canon)
+    #[cfg(feature = "watch")]
+    if args.watch {
+        let options = chewwy::watch::WatchOptions {
+            manage: options,
+            ..Default::default()
+        };
+        let managed = chewwy::watch::run(cfg, chewwy_root, options)
+            .change_context(CommandManageError)?;
+        return Ok(Output::ManageBatch { managed });
+    }
+    let files = if args.stdin {
+        manage::read_file_list(io::stdin().lock())
             .change_context(CommandManageError)
-            .attach_printable("cannot read search dir")?
-        {
-            let entry = entry
-                .change_context(CommandManageError)
-                .attach_printable("cannot read entry")?;
-            let path = entry.path();
-            items.push(path);
-        }
-        if items.is_empty() {
+            .attach_printable("can't read the files to manage from stdin")?
+    } else {
+        utils::expand_globs(args.files).change_context(CommandManageError)?
+    };
+    if args.verify_only {
+        if files.is_empty() && !args.stdin {
             return Err(CommandManageError)
-                .attach_printable("no item found in search directory");
-        }
-        println!("Choose an item");
-        for (i, item) in items.iter().enumerate() {
-            println!(
-                "[{i}] {}",
-                item.file_name()
-                    .unwrap_or_else(|| OsStr::new("???"))
-                    .to_string_lossy()
-            );
+                .attach_printable("`--verify-only` needs files to verify");
         }
-        print!("> ");
-        std::io::stdout()
-            .flush()
-            .change_context(CommandManageError)
-            .attach_printable("error flushing")?;
-        let mut input = String::new();
-        std::io::stdin()
-            .read_line(&mut input)
+        let verified = manage::run_verify(cfg, chewwy_root, &options, files)
             .change_context(CommandManageError)?;
-        let num = input
-            .trim()
-            .parse::<usize>()
-            .change_context(CommandManageError)
-            .attach_printable("what")?;
-        let Some(choosen_file) = items.get(num) else {
-            return Err(CommandManageError).attach_printable("no item exists");
+        return Ok(Output::Verify { verified });
+    }
+    // don't fall back to searching
+    if args.stdin && files.is_empty() && args.print_output_dir {
+        return managed_dirs(vec![]);
+    }
+    if args.stdin && files.is_empty() {
+        return Ok(Output::ManageBatch { managed: vec![] });
+    }
+    if files.len() <= 1 {
+        let options = ManageOptions {
+            file: files.into_iter().next(),
+            ..options
         };
-        file = Some(choosen_file.clone());
+        let summary = manage::run(cfg, chewwy_root, options)
+            .change_context(CommandManageError)?;
+        if args.print_output_dir {
+            return managed_dirs(vec![summary]);
+        }
+        return Ok(Output::Manage(summary));
     }
 
-    let compressed_file = file.unwrap();
-    let compressed_file: &Path = compressed_file.as_ref();
-    let canon_compressed_file_path = compressed_file
-        .canonicalize()
-        .change_context(CommandManageError)
-        .attach_printable("cannot canonicalize")?;
-    if !canon_compressed_file_path.is_file() {
-        return Err(CommandManageError).attach_printable_lazy(|| {
-            format!("{} is not a file", compressed_file.display())
+    let file_count = files.len();
+    let batch = manage::run_batch(
+        cfg,
+        chewwy_root,
+        options,
+        files,
+        args.keep_going,
+        args.jobs,
+    );
+    let mut failed = batch.failed.into_iter();
+    let Some((_, mut report)) = failed.next() else {
+        if args.print_output_dir {
+            return managed_dirs(batch.managed);
+        }
+        return Ok(Output::ManageBatch {
+            managed: batch.managed,
         });
+    };
+    let failed_count = failed.len() + 1;
+    for (_, other_report) in failed {
+        report.extend_one(other_report);
     }
-    let output_file_dir_name =
-        Path::new(canon_compressed_file_path.file_name().expect("file name"))
-            .with_extension("");
-    let output_file_dir_path;
+    Err(report)
+        .change_context(CommandManageError)
+        .attach_printable(format!(
+            "{failed_count} failed and {} managed of {file_count} files",
+            batch.managed.len()
+        ))
+}
 
-    match manage_cfg.output_file_action.c() {
-        cfg::OutputFileAction::DecompressToOutputDir => {
-            let Some(output_dir) = directories_cfg.output.c() else {
-                return Err(CommandManageError)
-                    .attach_printable("`output` directory is not configured");
-            };
-            let file_archiver =
-                chewwy::file_archiver::FileArchiver::new(formats_cfg);
-            output_file_dir_path =
-                Some(Path::new(output_dir).join(output_file_dir_name));
-            file_archiver
-                .decompress_to_dir(
-                    &canon_compressed_file_path,
-                    output_file_dir_path.as_ref().unwrap(),
-                )
-                .change_context(CommandManageError)
-                .attach_printable("cannont decompress")?;
-        }
-    }
-
-    if let Some(output_file_dir_path) = output_file_dir_path {
-        if *manage_cfg.smart_decompress_directory.c() {
-            println!("Unnesting dir");
-            match utils::unnest_dir(output_file_dir_path) {
-                Ok(())
-                | Err(utils::UnnestDirError::Empty)
-                | Err(utils::UnnestDirError::NotNested) => {}
-                Err(utils::UnnestDirError::Io(e)) => {
-                    return Err(e)
-                        .change_context(CommandManageError)
-                        .attach_printable("error unnesting dir");
+#[derive(Debug, Error)]
+#[error("command validate error")]
+struct CommandValidateError;
+
+fn command_validate(
+    cfg: &Cfg,
+    chewwy_root: Option<&Path>,
+) -> StackResult<(), CommandValidateError> {
+    for diagnostic in cfg.command_diagnostics() {
+        log::warn!("{diagnostic}");
+    }
+    let manage_cfg = cfg
+        .commands
+        .get()
+        .and_then(|commands| commands.manage.get());
+    let mut problems = vec![];
+    if let Some(manage_cfg) = manage_cfg {
+        let directories = manage_cfg.directories.get();
+        // relative directories are resolved from the root
+        if let (Some(directories), Some(root)) = (directories, chewwy_root) {
+            for overlap in directories.to_absolute(root).overlaps() {
+                if overlap.is_same() {
+                    problems.push(overlap.to_string());
+                } else {
+                    log::warn!("{overlap}");
                 }
             }
         }
-    }
-
-    match manage_cfg.compressed_file_action.c() {
-        cfg::CompressedFileAction::MoveToArchiveDir => {
-            let Some(archive_dir) = directories_cfg.archive.c() else {
-                return Err(CommandManageError)
-                    .attach_printable("`achive` directory is not configured");
+        let templates = [
+            ("output-dir-template", &manage_cfg.output_dir_template),
+            ("archive-name-template", &manage_cfg.archive_name_template),
+        ];
+        for (option, template) in templates {
+            let Some(template) = template.get() else {
+                continue;
             };
-
-            let file_name =
-                canon_compressed_file_path.file_name().expect("file name");
-            let new_path = archive_dir.join(file_name);
-            fs::rename(canon_compressed_file_path, new_path)
-                .change_context(CommandManageError)
-                .attach_printable("can't move achive to achive dir")?;
+            for name in manage::unknown_name_placeholders(template) {
+                log::warn!(
+                    "commands.manage.{option}: unknown placeholder `{{{name}}}`"
+                );
+            }
         }
-        cfg::CompressedFileAction::DoNothing => {}
     }
+    problems.extend(
+        cfg.validate()
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|path| format!("missing config `{path}`")),
+    );
+    for (name, formats) in cfg.format_name_conflicts() {
+        problems.push(format!("format name `{name}` is used by {formats:?}"));
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    let mut report = Err(error_stack::Report::new(CommandValidateError));
+    for problem in problems {
+        report = report.attach_printable(problem);
+    }
+    report
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_command_dispatch() {
+        assert!(default_command(None).is_none());
+        assert!(matches!(
+            default_command(Some("manage")),
+            Some(Command::Manage(ManageArgs {
+                keep_going: false,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            default_command(Some(" manage  --keep-going ")),
+            Some(Command::Manage(ManageArgs {
+                keep_going: true,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            default_command(Some("list a.zip")),
+            Some(Command::List { .. })
+        ));
+        // missing the directory to compress
+        assert!(default_command(Some("compress")).is_none());
+        assert!(default_command(Some("")).is_none());
+        assert!(default_command(Some("unknown")).is_none());
+        assert!(default_command(Some("completions bash")).is_none());
+    }
 }
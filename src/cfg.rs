@@ -1,36 +1,111 @@
-use crate::prelude::*;
+use crate::{prelude::*, utils};
 use std::{
     collections::{HashMap, HashSet},
     path::{Path, PathBuf},
 };
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 pub const FILE_NAME: &str = "cfg.toml";
 
 #[derive(Debug, Error)]
 pub enum LoadCfgError {
+    #[error("invalid cfg \"{}\" {source}", path.display())]
+    Invalid {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("io error reading cfg \"{}\" {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    /// From a config not read from a file
     #[error("invalid cfg {0}")]
-    Invalid(toml::de::Error),
-    #[error("io error {0}")]
-    Io(io::Error),
+    Parse(toml::de::Error),
 }
 
+/// Dotted path of an ignored key, `serde_ignored` shows `Option`s as `?`
+fn key_path(path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    let join = |parent: &Path, child: &dyn fmt::Display| {
+        let parent = key_path(parent);
+        if parent.is_empty() {
+            child.to_string()
+        } else {
+            format!("{parent}.{child}")
+        }
+    };
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => join(parent, index),
+        Path::Map { parent, key } => join(parent, key),
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => key_path(parent),
+    }
+}
+
+/// Unknown keys are logged as warnings
 pub fn load_cfg<P: AsRef<Path>>(cfg_file_path: P) -> Result<Cfg, LoadCfgError> {
-    let content =
-        fs::read_to_string(cfg_file_path).map_err(LoadCfgError::Io)?;
-    let cfg = toml::from_str(&content).map_err(LoadCfgError::Invalid)?;
+    let path = cfg_file_path.as_ref();
+    let (cfg, unknown_keys) = load_cfg_with_warnings(path)?;
+    for key in unknown_keys {
+        log::warn!("{}: unknown key `{key}`", path.display());
+    }
     Ok(cfg)
 }
 
+/// Like `load_cfg` but returns the paths of unknown keys, like
+/// `commands.manage.diretories`, instead of logging them
+pub fn load_cfg_with_warnings<P: AsRef<Path>>(
+    cfg_file_path: P,
+) -> Result<(Cfg, Vec<String>), LoadCfgError> {
+    let path = cfg_file_path.as_ref();
+    let content =
+        fs::read_to_string(path).map_err(|source| LoadCfgError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Cfg::from_toml_str_with_warnings(&content).map_err(|e| match e {
+        LoadCfgError::Parse(source) => LoadCfgError::Invalid {
+            path: path.to_path_buf(),
+            source,
+        },
+        e => e,
+    })
+}
+
+/// Like `load_cfg` but a missing file is `None`
+pub fn load_cfg_if_exists<P: AsRef<Path>>(
+    cfg_file_path: P,
+) -> Result<Option<Cfg>, LoadCfgError> {
+    match load_cfg(cfg_file_path) {
+        Ok(cfg) => Ok(Some(cfg)),
+        Err(LoadCfgError::Io { source, .. })
+            if source.kind() == io::ErrorKind::NotFound =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
 pub fn root_cfg_path<P: AsRef<Path>>(root: P) -> PathBuf {
     root.as_ref().join(crate::DOT_DIR).join(FILE_NAME)
 }
 
+/// `$XDG_CONFIG_HOME/chewwy/cfg.toml`, or `~/.config/chewwy/cfg.toml`
+pub fn global_cfg_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(crate::home_dir()?.join(".config")))?;
+    Some(config_home.join("chewwy").join(FILE_NAME))
+}
+
 #[derive(
     Debug,
     Default,
     Deserialize,
+    Serialize,
     Clone,
     Copy,
     PartialEq,
@@ -43,11 +118,36 @@ pub fn root_cfg_path<P: AsRef<Path>>(root: P) -> PathBuf {
 pub struct Configure<T>(pub Option<T>);
 
 impl<T> Configure<T> {
+    /// The value, panics when unset. Fine after merging the default config
+    /// or `Cfg::validate`
     pub fn c(&self) -> &T {
         self.0.as_ref().unwrap()
     }
+
+    /// The value, `None` when unset
+    pub fn get(&self) -> Option<&T> {
+        self.0.as_ref()
+    }
+
+    /// The value, `default` when unset
+    pub fn or<'a>(&'a self, default: &'a T) -> &'a T {
+        self.0.as_ref().unwrap_or(default)
+    }
+
+    /// The value, a `MissingField` error naming `field` when unset
+    pub fn c_or_err(&self, field: &str) -> StackResult<&T, MissingField> {
+        self.0
+            .as_ref()
+            .ok_or_else(|| error_stack::Report::new(MissingField(field.into())))
+    }
 }
 
+/// A config field is unset, with the path of the field like
+/// `options.color`
+#[derive(Debug, Error)]
+#[error("missing config field `{0}`")]
+pub struct MissingField(pub String);
+
 impl<T: Clone> Configure<T> {
     fn merge_value(&mut self, other: &Configure<T>) {
         if let (None, Some(o)) = (&self.0, &other.0) {
@@ -67,18 +167,18 @@ impl<T: Clone + StructMerge> Configure<T> {
 }
 
 impl<T: Clone + StructMerge> Configure<HashMap<String, T>> {
-    fn merge_struct_with_identical_key(
-        &mut self,
-        other: &Configure<HashMap<String, T>>,
-    ) {
+    /// Union of both maps, values under the same key are merged
+    fn merge_struct_by_key(&mut self, other: &Configure<HashMap<String, T>>) {
         match (&mut self.0, &other.0) {
             (None, Some(o)) => self.0 = Some(o.clone()),
             (Some(s), Some(o)) => {
-                for (k, v) in s.iter_mut() {
-                    let Some(ov) = o.get(k) else {
-                        continue;
-                    };
-                    v.struct_merge(ov);
+                for (k, ov) in o {
+                    match s.get_mut(k) {
+                        Some(v) => v.struct_merge(ov),
+                        None => {
+                            s.insert(k.clone(), ov.clone());
+                        }
+                    }
                 }
             }
             _ => {}
@@ -102,18 +202,413 @@ impl<T> From<Configure<T>> for Option<T> {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Cfg {
     pub formats: Configure<HashMap<String, Format>>,
     pub commands: Configure<CommandsCfg>,
+    pub options: Configure<OptionsCfg>,
 }
 
 impl StructMerge for Cfg {
     fn struct_merge(&mut self, other: &Cfg) {
-        self.formats.merge_struct_with_identical_key(&other.formats);
+        self.formats.merge_struct_by_key(&other.formats);
         self.commands.merge_struct(&other.commands);
+        self.options.merge_struct(&other.options);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ResolveExtendsError {
+    #[error("format \"{format}\" extends unknown format \"{extends}\"")]
+    UnknownFormat { format: String, extends: String },
+    #[error("formats extend each other in a cycle {0:?}")]
+    Cycle(Vec<String>),
+}
+
+impl Cfg {
+    /// Parse a config layer without reading a file. Unset fields stay unset,
+    /// `struct_merge` with `Cfg::default()` to fill them
+    pub fn from_toml_str(content: &str) -> Result<Cfg, LoadCfgError> {
+        toml::from_str(content).map_err(LoadCfgError::Parse)
+    }
+
+    /// Like `from_toml_str`, also returning the paths of keys that were
+    /// ignored because the config doesn't know them
+    pub fn from_toml_str_with_warnings(
+        content: &str,
+    ) -> Result<(Cfg, Vec<String>), LoadCfgError> {
+        let mut unknown_keys = vec![];
+        let cfg = serde_ignored::deserialize(
+            toml::Deserializer::new(content),
+            |path| unknown_keys.push(key_path(&path)),
+        )
+        .map_err(LoadCfgError::Parse)?;
+        Ok((cfg, unknown_keys))
+    }
+
+    /// Add or replace the format `name`
+    pub fn insert_format<S: Into<String>>(&mut self, name: S, format: Format) {
+        self.formats
+            .0
+            .get_or_insert_with(HashMap::new)
+            .insert(name.into(), format);
+    }
+
+    /// Fill every format that `extends` another with the fields it
+    /// doesn't set. Done after all config layers are merged
+    pub fn resolve_extends(&mut self) -> Result<(), ResolveExtendsError> {
+        let Some(formats) = &mut self.formats.0 else {
+            return Ok(());
+        };
+        let mut format_names = formats.keys().cloned().collect::<Vec<_>>();
+        format_names.sort();
+        let mut resolved = HashSet::new();
+        for format_name in format_names {
+            resolve_format_extends(
+                formats,
+                &format_name,
+                &mut resolved,
+                &mut vec![],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Format names claimed by more than one format as a key or in
+    /// `aliases`, with the formats claiming them, sorted
+    pub fn format_name_conflicts(&self) -> Vec<(String, Vec<String>)> {
+        let Some(formats) = &self.formats.0 else {
+            return vec![];
+        };
+        let mut claims = HashMap::<&String, HashSet<&String>>::new();
+        for (key, format) in formats {
+            claims.entry(key).or_default().insert(key);
+            for alias in format.aliases.0.iter().flatten() {
+                claims.entry(alias).or_default().insert(key);
+            }
+        }
+        let mut conflicts = claims
+            .into_iter()
+            .filter(|(_, keys)| keys.len() > 1)
+            .map(|(name, keys)| {
+                let mut keys = keys.into_iter().cloned().collect::<Vec<_>>();
+                keys.sort();
+                (name.clone(), keys)
+            })
+            .collect::<Vec<_>>();
+        conflicts.sort();
+        conflicts
+    }
+
+    /// Check that every field required by the commands is configured.
+    /// Returns the path of every missing field.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut missing = vec![];
+        let mut require = |is_set: bool, path: &str| {
+            if !is_set {
+                missing.push(path.to_string());
+            }
+        };
+
+        require(self.formats.0.is_some(), "formats");
+        if let Some(formats) = &self.formats.0 {
+            let mut format_names = formats.keys().collect::<Vec<_>>();
+            format_names.sort();
+            for format_name in format_names {
+                let format = &formats[format_name];
+                require(
+                    format.extensions.0.is_some(),
+                    &format!("formats.{format_name}.extensions"),
+                );
+                require(
+                    format.decompress.0.is_some(),
+                    &format!("formats.{format_name}.decompress"),
+                );
+            }
+        }
+
+        require(self.commands.0.is_some(), "commands");
+        let manage = self.commands.0.as_ref().map(|c| &c.manage);
+        require(manage.is_some_and(|m| m.0.is_some()), "commands.manage");
+        if let Some(manage) = manage.and_then(|m| m.0.as_ref()) {
+            require(
+                manage.smart_decompress_directory.0.is_some(),
+                "commands.manage.smart-decompress-directory",
+            );
+            require(
+                manage.smart_decompress_depth.0.is_some(),
+                "commands.manage.smart-decompress-depth",
+            );
+            require(
+                manage.single_file_output.0.is_some(),
+                "commands.manage.single-file-output",
+            );
+            require(
+                manage.empty_output.0.is_some(),
+                "commands.manage.empty-output",
+            );
+            require(
+                manage.search_file.0.is_some(),
+                "commands.manage.search-file",
+            );
+            require(
+                manage.search_ignore.0.is_some(),
+                "commands.manage.search-ignore",
+            );
+            require(
+                manage.search_sort.0.is_some(),
+                "commands.manage.search-sort",
+            );
+            require(
+                manage.search_depth.0.is_some(),
+                "commands.manage.search-depth",
+            );
+            require(
+                manage.search_max_entries.0.is_some(),
+                "commands.manage.search-max-entries",
+            );
+            require(
+                manage.follow_symlinks.0.is_some(),
+                "commands.manage.follow-symlinks",
+            );
+            require(
+                manage.output_file_action.0.is_some(),
+                "commands.manage.output-file-action",
+            );
+            require(
+                manage.compressed_file_action.0.is_some(),
+                "commands.manage.compressed-file-action",
+            );
+            require(
+                manage.directories.0.is_some(),
+                "commands.manage.directories",
+            );
+            require(
+                manage.output_dir_template.0.is_some(),
+                "commands.manage.output-dir-template",
+            );
+            require(
+                manage.create_missing_dirs.0.is_some(),
+                "commands.manage.create-missing-dirs",
+            );
+            require(
+                manage.archive_name_template.0.is_some(),
+                "commands.manage.archive-name-template",
+            );
+            require(
+                manage.archive_on_exists.0.is_some(),
+                "commands.manage.archive-on-exists",
+            );
+            require(
+                manage.confine_directories.0.is_some(),
+                "commands.manage.confine-directories",
+            );
+            require(
+                manage.post_decompress.0.is_some(),
+                "commands.manage.post-decompress",
+            );
+            require(
+                manage.post_decompress_fatal.0.is_some(),
+                "commands.manage.post-decompress-fatal",
+            );
+            require(
+                manage.verify_checksum.0.is_some(),
+                "commands.manage.verify-checksum",
+            );
+            require(
+                manage.require_checksum.0.is_some(),
+                "commands.manage.require-checksum",
+            );
+            require(
+                manage.space_check.0.is_some(),
+                "commands.manage.space-check",
+            );
+            require(
+                manage.space_ratio.0.is_some(),
+                "commands.manage.space-ratio",
+            );
+            require(
+                manage.min_free_space.0.is_some(),
+                "commands.manage.min-free-space",
+            );
+            require(
+                manage.write_manifest.0.is_some(),
+                "commands.manage.write-manifest",
+            );
+            if let Some(directories) = &manage.directories.0 {
+                require(
+                    directories.search.0.is_some(),
+                    "commands.manage.directories.search",
+                );
+                require(
+                    directories.output.0.is_some(),
+                    "commands.manage.directories.output",
+                );
+                require(
+                    directories.archive.0.is_some(),
+                    "commands.manage.directories.archive",
+                );
+            }
+        }
+        let compress = self.commands.0.as_ref().map(|c| &c.compress);
+        require(compress.is_some_and(|c| c.0.is_some()), "commands.compress");
+        if let Some(compress) = compress.and_then(|c| c.0.as_ref()) {
+            require(compress.format.0.is_some(), "commands.compress.format");
+            require(
+                compress.source_dir_action.0.is_some(),
+                "commands.compress.source-dir-action",
+            );
+        }
+
+        require(self.options.0.is_some(), "options");
+        if let Some(options) = &self.options.0 {
+            require(options.quiet.0.is_some(), "options.quiet");
+            require(options.color.0.is_some(), "options.color");
+            require(
+                options.case_sensitive_extensions.0.is_some(),
+                "options.case-sensitive-extensions",
+            );
+            require(options.redact.0.is_some(), "options.redact");
+        }
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    /// Check the placeholders used in every command
+    pub fn command_diagnostics(&self) -> Vec<CommandDiagnostic> {
+        let mut diagnostics = vec![];
+        let Some(formats) = &self.formats.0 else {
+            return diagnostics;
+        };
+        let mut format_names = formats.keys().collect::<Vec<_>>();
+        format_names.sort();
+        for format_name in format_names {
+            let format = &formats[format_name];
+            let lists = [
+                ("decompress", &format.decompress),
+                ("compress", &format.compress),
+            ];
+            for (list, commands) in lists {
+                let Some(commands) = &commands.0 else {
+                    continue;
+                };
+                for (index, command) in commands.iter().enumerate() {
+                    let mut diagnostic = |kind| {
+                        diagnostics.push(CommandDiagnostic {
+                            format: format_name.clone(),
+                            list,
+                            index,
+                            kind,
+                        })
+                    };
+                    let mut used_known = false;
+                    for name in command.placeholders() {
+                        if PLACEHOLDERS.contains(&name) {
+                            used_known = true;
+                        } else {
+                            diagnostic(
+                                CommandDiagnosticKind::UnknownPlaceholder(
+                                    name.to_string(),
+                                ),
+                            );
+                        }
+                    }
+                    if list == "decompress" && !used_known {
+                        diagnostic(CommandDiagnosticKind::NoPlaceholder);
+                    }
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandDiagnostic {
+    pub format: String,
+    /// The command list in the format, e.g. `decompress`
+    pub list: &'static str,
+    pub index: usize,
+    pub kind: CommandDiagnosticKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandDiagnosticKind {
+    UnknownPlaceholder(String),
+    /// A decompress command using none of the `PLACEHOLDERS`
+    NoPlaceholder,
+}
+
+impl fmt::Display for CommandDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "formats.{}.{}[{}]: ", self.format, self.list, self.index)?;
+        match &self.kind {
+            CommandDiagnosticKind::UnknownPlaceholder(name) => {
+                write!(f, "unknown placeholder `{{{name}}}`")
+            }
+            CommandDiagnosticKind::NoPlaceholder => {
+                write!(f, "no placeholder is used")
+            }
+        }
+    }
+}
+
+fn resolve_format_extends(
+    formats: &mut HashMap<String, Format>,
+    format_name: &str,
+    resolved: &mut HashSet<String>,
+    visiting: &mut Vec<String>,
+) -> Result<(), ResolveExtendsError> {
+    if resolved.contains(format_name) {
+        return Ok(());
     }
+    if let Some(i) = visiting.iter().position(|v| v == format_name) {
+        let mut cycle = visiting[i..].to_vec();
+        cycle.push(format_name.to_string());
+        return Err(ResolveExtendsError::Cycle(cycle));
+    }
+    if let Some(extends) = formats[format_name].extends.0.clone() {
+        let Some(extends) = resolve_format_name(formats, &extends).cloned()
+        else {
+            return Err(ResolveExtendsError::UnknownFormat {
+                format: format_name.to_string(),
+                extends,
+            });
+        };
+        visiting.push(format_name.to_string());
+        resolve_format_extends(formats, &extends, resolved, visiting)?;
+        visiting.pop();
+        let parent = formats[&extends].clone();
+        formats
+            .get_mut(format_name)
+            .expect("format")
+            .struct_merge(&parent);
+    }
+    resolved.insert(format_name.to_string());
+    Ok(())
+}
+
+/// The key of the format named `name`, either its key or one of its
+/// `aliases`
+pub fn resolve_format_name<'a>(
+    formats: &'a HashMap<String, Format>,
+    name: &str,
+) -> Option<&'a String> {
+    if let Some((key, _)) = formats.get_key_value(name) {
+        return Some(key);
+    }
+    formats
+        .iter()
+        .filter(|(_, format)| {
+            format.aliases.0.as_ref().is_some_and(|a| a.contains(name))
+        })
+        .map(|(key, _)| key)
+        .min()
 }
 
 impl Default for Cfg {
@@ -122,102 +617,639 @@ impl Default for Cfg {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct Format {
-    /// Search for the following extensions
+    /// Search for the following extensions. `*` matches any file with an
+    /// extension no other format claims, only one format may have it
     pub extensions: Configure<HashSet<String>>,
     /// Will use the first command that exists
     pub decompress: Configure<Vec<Command>>,
-    // /// Will use the first command that exists
-    // pub compress: Vec<Command>,
+    /// Other names to choose the format by, like in `extends` and
+    /// `compress --format`
+    pub aliases: Configure<HashSet<String>>,
+    /// How `decompress` combines with the one of the config below, like
+    /// the default config. Replaces it when unset
+    pub merge_commands: Configure<MergeStrategy>,
+    /// Formats claiming the same extension are chosen by higher priority.
+    /// Defaults to 0
+    pub priority: Configure<i32>,
+    /// Will use the first command that exists.
+    /// `{FILE}` is the archive to create, `{DIR}` the directory to compress
+    pub compress: Configure<Vec<Command>>,
+    /// Extension given to archives created with this format
+    pub canonical_extension: Configure<String>,
+    /// Will use the first command that exists to write the decompressed
+    /// content of a single-stream file to stdout. Only `{FILE}` and
+    /// `{BASENAME}` are meaningful
+    pub decompress_stdout: Configure<Vec<Command>>,
+    /// Will use the first command that exists to decompress stdin to
+    /// stdout, for `--pipe`. There is no file or directory to substitute
+    pub decompress_pipe: Configure<Vec<Command>>,
+    /// Will use the first command that exists to list the entries of an
+    /// archive. Only `{FILE}` and `{BASENAME}` are meaningful
+    pub list: Configure<Vec<Command>>,
+    /// Will use the first command that exists to test the integrity of an
+    /// archive without extracting it, for `manage --verify-only`. Only
+    /// `{FILE}` and `{BASENAME}` are meaningful
+    pub verify: Configure<Vec<Command>>,
+    /// Name of a format to inherit unset fields from.
+    /// Inherited extensions are claimed by both formats, so set a higher
+    /// `priority` to take them over
+    pub extends: Configure<String>,
+    /// Leading directories left out of the decompressed content, like
+    /// `tar --strip-components`. Commands using `{STRIP}` strip natively,
+    /// after the others as many single nested directories are collapsed,
+    /// which stops early at a level with more than one entry
+    pub strip_components: Configure<u32>,
+    /// When a `decompress` command exits with an error, try the next one
+    /// instead of failing. Defaults to false
+    pub try_next_on_failure: Configure<bool>,
+    /// MIME types of this format like `application/zip`, to find it for
+    /// content without a file name
+    pub mime_types: Configure<HashSet<String>>,
+    /// Encrypted archives of this format can't be decompressed without a
+    /// password, asked for when none is given. Defaults to false
+    pub needs_password: Configure<bool>,
+    /// File with the key of the archives. Its path is substituted for
+    /// `{KEY_FILE}` and its content without the trailing newline for
+    /// `{KEY}`. Relative to the chewwy root, it must exist when the format
+    /// is used
+    pub key_file: Configure<Option<PathBuf>>,
+}
+
+impl Format {
+    /// `key_file` resolved from `chewwy_root` when relative, or from the
+    /// current directory without one
+    pub fn key_file_path(&self, chewwy_root: Option<&Path>) -> Option<PathBuf> {
+        let key_file = self.key_file.0.as_ref()?.as_ref()?;
+        Some(match chewwy_root {
+            Some(root) => utils::normalize_lexically(root.join(key_file)),
+            None => key_file.clone(),
+        })
+    }
+
+    /// `canonical_extension`, or the first of the sorted `extensions`
+    pub fn archive_extension(&self) -> Option<&str> {
+        if let Some(extension) = &self.canonical_extension.0 {
+            return Some(extension);
+        }
+        let extensions = self.extensions.0.as_ref()?;
+        extensions
+            .iter()
+            .filter(|e| *e != crate::file_archiver::WILDCARD_EXTENSION)
+            .min()
+            .map(|e| e.as_str())
+    }
 }
 
 impl StructMerge for Format {
     fn struct_merge(&mut self, other: &Format) {
         self.extensions.merge_value(&other.extensions);
-        self.decompress.merge_value(&other.decompress);
+        self.aliases.merge_value(&other.aliases);
+        self.merge_decompress(other);
+        self.priority.merge_value(&other.priority);
+        self.compress.merge_value(&other.compress);
+        self.canonical_extension
+            .merge_value(&other.canonical_extension);
+        self.extends.merge_value(&other.extends);
+        self.decompress_stdout.merge_value(&other.decompress_stdout);
+        self.decompress_pipe.merge_value(&other.decompress_pipe);
+        self.list.merge_value(&other.list);
+        self.verify.merge_value(&other.verify);
+        self.strip_components.merge_value(&other.strip_components);
+        self.try_next_on_failure
+            .merge_value(&other.try_next_on_failure);
+        self.mime_types.merge_value(&other.mime_types);
+        self.needs_password.merge_value(&other.needs_password);
+        self.key_file.merge_value(&other.key_file);
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Format {
+    fn merge_decompress(&mut self, other: &Format) {
+        let strategy = self.merge_commands.0.unwrap_or_default();
+        let (Some(commands), Some(other_commands)) =
+            (&mut self.decompress.0, &other.decompress.0)
+        else {
+            self.decompress.merge_value(&other.decompress);
+            self.merge_commands.merge_value(&other.merge_commands);
+            return;
+        };
+        match strategy {
+            MergeStrategy::Replace => return,
+            MergeStrategy::Prepend => {
+                commands.extend(other_commands.iter().cloned())
+            }
+            MergeStrategy::Append => {
+                commands.splice(0..0, other_commands.iter().cloned());
+            }
+        }
+        // the combined commands merge with the config below like `other`'s
+        self.merge_commands = other.merge_commands;
+    }
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeStrategy {
+    /// Only use these commands
+    #[default]
+    Replace,
+    /// Try these commands before the ones below
+    Prepend,
+    /// Try these commands after the ones below
+    Append,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct Command {
     pub path: String,
     /// `{FILE}` for origin file path
-    /// `{DIR} for output directory path
+    /// `{DIR}` for output directory path
+    /// `{BASENAME}` for the origin file name without its last extension,
+    /// the same as the output directory name. `a.tar.gz` is `a.tar`
+    /// `{PASSWORD}` for the password of an encrypted archive, kept as is
+    /// when no password is given
+    /// `{STRIP}` for the format's `strip-components`, kept as is when unset
+    /// `{ROOT}` for the chewwy root, `{OUTPUT_DIR}` and `{ARCHIVE_DIR}` for
+    /// the configured `output` and `archive` directories, kept as is when
+    /// unknown like outside of `manage`
+    /// `{{` and `}}` for literal braces
     pub args: Vec<String>,
+    /// Working directory of the command, with the same placeholders as
+    /// `args`. Created if missing. Inherited when unset
+    pub cwd: Option<String>,
+    /// Environment variables set for the command, values have the same
+    /// placeholders as `args`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Exit codes counted as success, like `[0, 1]` for `unzip` which
+    /// exits with 1 on warnings. Defaults to `[0]`
+    #[serde(rename = "success-codes")]
+    pub success_codes: Configure<Vec<i32>>,
+}
+
+/// Placeholders substituted in `Command::args`
+pub const PLACEHOLDERS: &[&str] = &[
+    "FILE",
+    "DIR",
+    "BASENAME",
+    "PASSWORD",
+    "STRIP",
+    "KEY",
+    "KEY_FILE",
+    "ROOT",
+    "OUTPUT_DIR",
+    "ARCHIVE_DIR",
+];
+
+/// Placeholders whose values are hidden when showing a command
+pub const SENSITIVE_PLACEHOLDERS: &[&str] = &["PASSWORD", "KEY"];
+
+/// The `key-file` of a format, for `{KEY_FILE}` and `{KEY}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFile {
+    pub path: String,
+    /// The content without the trailing newline, only read when a command
+    /// uses `{KEY}`
+    pub key: Option<String>,
+}
+
+/// Values substituted for the `PLACEHOLDERS` of a command, the `None`
+/// ones are kept as is
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Substitutions<'a> {
+    pub file: &'a str,
+    pub dir: &'a str,
+    pub password: Option<&'a str>,
+    pub strip_components: Option<u32>,
+    pub key: Option<&'a KeyFile>,
+    pub root: Option<&'a Path>,
+    pub output_dir: Option<&'a Path>,
+    pub archive_dir: Option<&'a Path>,
+}
+
+/// A command ready to spawn, knowing which of its values to hide when
+/// it's shown in logs and errors
+#[derive(Debug)]
+pub struct FormattedCommand {
+    pub command: process::Command,
+    /// Indices of args rendered from `SENSITIVE_PLACEHOLDERS`
+    pub sensitive_args: Vec<usize>,
+    /// Variables whose values are rendered from `SENSITIVE_PLACEHOLDERS`
+    pub sensitive_env: Vec<String>,
+}
+
+impl FormattedCommand {
+    /// The command as `cd "dir" && KEY="value" "path" "args"...` with the
+    /// sensitive values, and the ones containing any of `redact`, shown
+    /// as `***`
+    pub fn redacted(&self, redact: &[String]) -> String {
+        let hide = |value: &OsStr, sensitive: bool| {
+            let value = value.to_string_lossy();
+            let matches_redact = redact
+                .iter()
+                .any(|r| !r.is_empty() && value.contains(r.as_str()));
+            if sensitive || matches_redact {
+                "***".to_string()
+            } else {
+                format!("{value:?}")
+            }
+        };
+        let mut shown = String::new();
+        if let Some(cwd) = self.command.get_current_dir() {
+            shown.push_str(&format!("cd {cwd:?} && "));
+        }
+        let mut envs = self.command.get_envs().collect::<Vec<_>>();
+        envs.sort();
+        for (key, value) in envs {
+            let Some(value) = value else {
+                continue;
+            };
+            let key = key.to_string_lossy();
+            let sensitive = self.sensitive_env.iter().any(|k| *k == key);
+            shown.push_str(&format!("{key}={} ", hide(value, sensitive)));
+        }
+        shown.push_str(&format!("{:?}", self.command.get_program()));
+        for (i, arg) in self.command.get_args().enumerate() {
+            shown.push(' ');
+            shown.push_str(&hide(arg, self.sensitive_args.contains(&i)));
+        }
+        shown
+    }
 }
 
 impl Command {
+    pub fn new<S: Into<String>>(path: S, args: &[&str]) -> Command {
+        Command {
+            path: path.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the command succeeded by its `success-codes`
+    pub fn succeeded(&self, status: process::ExitStatus) -> bool {
+        match self.success_codes.get() {
+            Some(codes) => status.code().is_some_and(|c| codes.contains(&c)),
+            None => status.success(),
+        }
+    }
+
+    /// Names of every `{...}` placeholder in `args`, `cwd` and `env`
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        let args = self.args.iter().chain(&self.cwd).chain(self.env.values());
+        args.flat_map(|arg| {
+            utils::template_tokens(arg).into_iter().filter_map(|token| {
+                match token {
+                    utils::TemplateToken::Placeholder(name) => Some(name),
+                    utils::TemplateToken::Text(_) => None,
+                }
+            })
+        })
+    }
+
     pub fn decompress_command_format(
         &self,
-        file: &str,
-        dir: &str,
-    ) -> process::Command {
+        substitutions: &Substitutions,
+    ) -> FormattedCommand {
+        let Substitutions { file, dir, key, .. } = *substitutions;
+        let basename = Path::new(file)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let strip_components =
+            substitutions.strip_components.map(|n| n.to_string());
+        let root = substitutions.root.map(Path::to_string_lossy);
+        let output_dir = substitutions.output_dir.map(Path::to_string_lossy);
+        let archive_dir = substitutions.archive_dir.map(Path::to_string_lossy);
+        let render = |arg: &str| {
+            utils::render_template(arg, |name| match name {
+                "FILE" => Some(file),
+                "DIR" => Some(dir),
+                "BASENAME" => Some(&basename),
+                "PASSWORD" => substitutions.password,
+                "STRIP" => strip_components.as_deref(),
+                "KEY_FILE" => key.map(|key| key.path.as_str()),
+                "KEY" => key.and_then(|key| key.key.as_deref()),
+                "ROOT" => root.as_deref(),
+                "OUTPUT_DIR" => output_dir.as_deref(),
+                "ARCHIVE_DIR" => archive_dir.as_deref(),
+                _ => None,
+            })
+        };
+        let is_sensitive = |arg: &str| {
+            utils::template_tokens(arg).into_iter().any(|token| {
+                matches!(
+                    token,
+                    utils::TemplateToken::Placeholder(name)
+                        if SENSITIVE_PLACEHOLDERS.contains(&name)
+                )
+            })
+        };
         let mut command = process::Command::new(&self.path);
-        command.args(
-            self.args
+        command.args(self.args.iter().map(|arg| render(arg)));
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(render(cwd));
+        }
+        command.envs(self.env.iter().map(|(k, v)| (k, render(v))));
+        FormattedCommand {
+            command,
+            sensitive_args: (0..self.args.len())
+                .filter(|&i| is_sensitive(&self.args[i]))
+                .collect(),
+            sensitive_env: self
+                .env
                 .iter()
-                .map(|arg| arg.replace("{FILE}", file).replace("{DIR}", dir)),
-        );
-        command
+                .filter(|(_, v)| is_sensitive(v))
+                .map(|(k, _)| k.clone())
+                .collect(),
+        }
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Settings shared by every command
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct OptionsCfg {
+    /// Don't print informational messages
+    pub quiet: Configure<bool>,
+    /// Whether errors are colored
+    pub color: Configure<ColorChoice>,
+    /// Match file extensions to format extensions exactly instead of
+    /// ignoring case
+    pub case_sensitive_extensions: Configure<bool>,
+    /// Command args and environment values containing any of these are
+    /// shown as `***` in logs and errors, like `{PASSWORD}` ones
+    pub redact: Configure<Vec<String>>,
+    /// Command line run when `chewer` is given no command, without the
+    /// program name like `manage --keep-going`. Help is printed when
+    /// unset or the command line is incomplete
+    pub default_command: Configure<String>,
+}
+
+impl StructMerge for OptionsCfg {
+    fn struct_merge(&mut self, other: &OptionsCfg) {
+        self.quiet.merge_value(&other.quiet);
+        self.color.merge_value(&other.color);
+        self.case_sensitive_extensions
+            .merge_value(&other.case_sensitive_extensions);
+        self.redact.merge_value(&other.redact);
+        self.default_command.merge_value(&other.default_command);
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorChoice {
+    /// Color when printing to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color what is printed to a stream, `is_terminal` or not
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                is_terminal
+                    && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CommandsCfg {
     pub manage: Configure<ManageCommandCfg>,
+    pub compress: Configure<CompressCommandCfg>,
 }
 
 impl StructMerge for CommandsCfg {
     fn struct_merge(&mut self, other: &CommandsCfg) {
         self.manage.merge_struct(&other.manage);
+        self.compress.merge_struct(&other.compress);
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CompressCommandCfg {
+    /// Name of the format to compress with
+    pub format: Configure<String>,
+    /// What to do with the source directory after compressing
+    pub source_dir_action: Configure<SourceDirAction>,
+}
+
+impl StructMerge for CompressCommandCfg {
+    fn struct_merge(&mut self, other: &CompressCommandCfg) {
+        self.format.merge_value(&other.format);
+        self.source_dir_action.merge_value(&other.source_dir_action);
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceDirAction {
+    #[default]
+    DoNothing,
+    /// Only once the archive exists and is not empty
+    Delete,
+    /// Relative `path` is resolved from the chewwy root
+    MoveToDir { path: PathBuf },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 pub struct ManageCommandCfg {
-    /// Smart decompress output to a no nest directory
+    /// When the decompressed directory only has a directory, replace it
+    /// with that directory's content. `output/a/a/*` becomes `output/a/*`
     pub smart_decompress_directory: Configure<bool>,
+    /// How many nested single directories `smart-decompress-directory`
+    /// collapses. `output/a/a/b/*` becomes `output/a/*` with 2
+    pub smart_decompress_depth: Configure<usize>,
+    /// When the decompressed directory only has a file
+    pub single_file_output: Configure<SingleFileOutput>,
+    /// When nothing was decompressed
+    pub empty_output: Configure<EmptyOutput>,
     pub search_file: Configure<bool>,
+    /// Glob patterns of file names left out when searching, like `.*` for
+    /// hidden files
+    pub search_ignore: Configure<Vec<String>>,
+    /// Order of the files found when searching
+    pub search_sort: Configure<SortKey>,
+    /// Levels of subdirectories searched too, 0 for only the files directly
+    /// in the search directory
+    pub search_depth: Configure<usize>,
+    /// Error instead of listing more files than this when searching
+    pub search_max_entries: Configure<usize>,
+    /// List symlinks to directories when searching, they're skipped
+    /// otherwise so the search can't leave the search directory
+    pub follow_symlinks: Configure<bool>,
     /// What to do with the output file after finishing
     pub output_file_action: Configure<OutputFileAction>,
     /// What to do with the compressed file after finishing
     pub compressed_file_action: Configure<CompressedFileAction>,
     pub directories: Configure<Directories>,
+    /// Name of the directory a file is decompressed to in the `output`
+    /// directory. `{NAME}` is the file name without its last extension,
+    /// `{EXT}` the last extension, `{FILE_NAME}` the whole file name,
+    /// `{DATE}` and `{TIME}` the current UTC date `2024-12-31` and time
+    /// `12-30-05`
+    pub output_dir_template: Configure<String>,
+    /// Create the `output` and `archive` directories when they're missing
+    pub create_missing_dirs: Configure<bool>,
+    /// Name of the compressed file moved to the `archive` directory, with
+    /// the same placeholders as `output-dir-template`
+    pub archive_name_template: Configure<String>,
+    /// What to do when the file moved to the `archive` directory, or to
+    /// `move-to-dir`, exists
+    pub archive_on_exists: Configure<OnExists>,
+    /// Program and arguments run after a file is managed successfully.
+    /// `{OUTPUT_DIR}` is the decompressed directory, `{ARCHIVE}` where the
+    /// compressed file is now. Empty to run nothing
+    pub post_decompress: Configure<Vec<String>>,
+    /// Whether the failure of `post-decompress` fails the manage
+    pub post_decompress_fatal: Configure<bool>,
+    /// Error if a directory resolves outside the chewwy root
+    pub confine_directories: Configure<bool>,
+    /// Verify the file against a `<file>.sha256` or `<file>.md5` sidecar
+    /// before decompressing
+    pub verify_checksum: Configure<bool>,
+    /// Error instead of warn when verifying and no sidecar exists
+    pub require_checksum: Configure<bool>,
+    /// Error before decompressing when the output filesystem lacks the
+    /// estimated space
+    pub space_check: Configure<bool>,
+    /// Decompressed size estimated as this times the compressed size
+    pub space_ratio: Configure<f64>,
+    /// Bytes that must stay free after decompressing
+    pub min_free_space: Configure<u64>,
+    /// Write a `.chewwy-manifest.toml` in the output directory with the
+    /// archive, its checksum, the format and command used
+    pub write_manifest: Configure<bool>,
 }
 
 impl StructMerge for ManageCommandCfg {
     fn struct_merge(&mut self, other: &ManageCommandCfg) {
         self.smart_decompress_directory
             .merge_value(&other.smart_decompress_directory);
+        self.smart_decompress_depth
+            .merge_value(&other.smart_decompress_depth);
+        self.single_file_output
+            .merge_value(&other.single_file_output);
+        self.empty_output.merge_value(&other.empty_output);
         self.search_file.merge_value(&other.search_file);
+        self.search_ignore.merge_value(&other.search_ignore);
+        self.search_sort.merge_value(&other.search_sort);
+        self.search_depth.merge_value(&other.search_depth);
+        self.search_max_entries
+            .merge_value(&other.search_max_entries);
+        self.follow_symlinks.merge_value(&other.follow_symlinks);
         self.output_file_action
             .merge_value(&other.output_file_action);
         self.compressed_file_action
             .merge_value(&other.compressed_file_action);
         self.directories.merge_struct(&other.directories);
+        self.output_dir_template
+            .merge_value(&other.output_dir_template);
+        self.create_missing_dirs
+            .merge_value(&other.create_missing_dirs);
+        self.archive_name_template
+            .merge_value(&other.archive_name_template);
+        self.archive_on_exists.merge_value(&other.archive_on_exists);
+        self.confine_directories
+            .merge_value(&other.confine_directories);
+        self.post_decompress.merge_value(&other.post_decompress);
+        self.post_decompress_fatal
+            .merge_value(&other.post_decompress_fatal);
+        self.verify_checksum.merge_value(&other.verify_checksum);
+        self.require_checksum.merge_value(&other.require_checksum);
+        self.space_check.merge_value(&other.space_check);
+        self.space_ratio.merge_value(&other.space_ratio);
+        self.min_free_space.merge_value(&other.min_free_space);
+        self.write_manifest.merge_value(&other.write_manifest);
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum OutputFileAction {
     #[default]
     DecompressToOutputDir,
+    /// Write the decompressed content to stdout with the format's
+    /// `decompress-stdout` commands
+    ToStdout,
 }
 
-#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum SingleFileOutput {
+    /// Keep the file in the decompressed directory, `output/a/a.txt`
+    #[default]
+    Keep,
+    /// Replace the decompressed directory with the file, `output/a.txt`
+    Unwrap,
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmptyOutput {
+    /// Keep the empty directory and warn
+    #[default]
+    Keep,
+    /// Remove the empty directory and warn
+    Remove,
+    Error,
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortKey {
+    #[default]
+    Name,
+    /// Most recently modified first
+    ModifiedDesc,
+    /// Largest first
+    SizeDesc,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnExists {
+    /// Append the first unused number to the name, `a-1.zip`
+    #[default]
+    Number,
+    Error,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum CompressedFileAction {
     #[default]
     MoveToArchiveDir,
+    /// Relative `path` is resolved from the chewwy root
+    MoveToDir {
+        path: PathBuf,
+    },
     DoNothing,
 }
 
 /// Will resolve path variable and stuff
-#[derive(Debug, Default, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct Directories {
     pub search: Configure<Option<PathBuf>>,
     pub output: Configure<Option<PathBuf>>,
@@ -225,27 +1257,17 @@ pub struct Directories {
 }
 
 impl Directories {
+    /// Relative paths are joined onto `relative_to`, then every path is
+    /// normalized lexically
     pub fn to_absolute<P: AsRef<Path>>(&self, relative_to: P) -> Directories {
         let search = self.search.c().as_ref().map(|search| {
-            if search.is_relative() {
-                relative_to.as_ref().join(search)
-            } else {
-                search.to_owned()
-            }
+            utils::normalize_lexically(relative_to.as_ref().join(search))
         });
         let output = self.output.c().as_ref().map(|output| {
-            if output.is_relative() {
-                relative_to.as_ref().join(output)
-            } else {
-                output.to_owned()
-            }
+            utils::normalize_lexically(relative_to.as_ref().join(output))
         });
         let archive = self.archive.c().as_ref().map(|archive| {
-            if archive.is_relative() {
-                relative_to.as_ref().join(archive)
-            } else {
-                archive.to_owned()
-            }
+            utils::normalize_lexically(relative_to.as_ref().join(archive))
         });
         Directories {
             search: Configure(Some(search)),
@@ -255,6 +1277,94 @@ impl Directories {
     }
 }
 
+impl Directories {
+    /// The first configured directory that is not inside `root`
+    pub fn find_outside<P: AsRef<Path>>(&self, root: P) -> Option<&Path> {
+        let root = utils::normalize_lexically(root);
+        [&self.search, &self.output, &self.archive]
+            .into_iter()
+            .filter_map(|dir| dir.0.as_ref()?.as_deref())
+            .find(|dir| !utils::normalize_lexically(dir).starts_with(&root))
+    }
+
+    /// Pairs of configured directories that are the same or nested, like
+    /// an `archive` directory inside the `search` one where archived files
+    /// would be found again. Paths are canonicalized when they exist
+    pub fn overlaps(&self) -> Vec<DirectoryOverlap> {
+        let dirs = [
+            ("search", &self.search),
+            ("output", &self.output),
+            ("archive", &self.archive),
+        ]
+        .into_iter()
+        .filter_map(|(name, dir)| {
+            let dir = dir.0.as_ref()?.as_deref()?;
+            let dir = fs::canonicalize(dir)
+                .unwrap_or_else(|_| utils::normalize_lexically(dir));
+            Some((name, dir))
+        })
+        .collect::<Vec<_>>();
+        let mut overlaps = vec![];
+        for (i, (name, dir)) in dirs.iter().enumerate() {
+            for (other_name, other) in &dirs[i + 1..] {
+                let (outer, inner) = if other.starts_with(dir) {
+                    ((name, dir), (other_name, other))
+                } else if dir.starts_with(other) {
+                    ((other_name, other), (name, dir))
+                } else {
+                    continue;
+                };
+                overlaps.push(DirectoryOverlap {
+                    outer: outer.0,
+                    outer_path: outer.1.clone(),
+                    inner: inner.0,
+                    inner_path: inner.1.clone(),
+                });
+            }
+        }
+        overlaps
+    }
+}
+
+/// See `Directories::overlaps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryOverlap {
+    pub outer: &'static str,
+    pub outer_path: PathBuf,
+    pub inner: &'static str,
+    pub inner_path: PathBuf,
+}
+
+impl DirectoryOverlap {
+    /// Both are the same directory, not only nested
+    pub fn is_same(&self) -> bool {
+        self.outer_path == self.inner_path
+    }
+}
+
+impl fmt::Display for DirectoryOverlap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_same() {
+            write!(
+                f,
+                "{} and {} directories are both `{}`",
+                self.outer,
+                self.inner,
+                self.outer_path.display()
+            )
+        } else {
+            write!(
+                f,
+                "{} directory `{}` is inside the {} directory `{}`",
+                self.inner,
+                self.inner_path.display(),
+                self.outer,
+                self.outer_path.display()
+            )
+        }
+    }
+}
+
 impl StructMerge for Directories {
     fn struct_merge(&mut self, other: &Directories) {
         self.search.merge_value(&other.search);
@@ -262,3 +1372,682 @@ impl StructMerge for Directories {
         self.archive.merge_value(&other.archive);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolve_format_name_aliases() {
+        let mut cfg = Cfg::from_toml_str(
+            r#"
+            [formats.targz]
+            aliases = ["tgz", "tar.gz"]
+            [formats.child]
+            extends = "tgz"
+            "#,
+        )
+        .unwrap();
+        let formats = cfg.formats.c();
+        let resolve = |name| resolve_format_name(formats, name);
+        assert_eq!(Some(&"targz".to_string()), resolve("targz"));
+        assert_eq!(Some(&"targz".to_string()), resolve("tgz"));
+        assert_eq!(None, resolve("zip"));
+        assert!(cfg.format_name_conflicts().is_empty());
+        cfg.resolve_extends().unwrap();
+        assert_eq!(
+            cfg.formats.c()["targz"].aliases.0,
+            cfg.formats.c()["child"].aliases.0
+        );
+
+        let cfg = Cfg::from_toml_str(
+            r#"
+            [formats.targz]
+            aliases = ["tgz"]
+            [formats.tgz]
+            [formats.other]
+            aliases = ["tgz", "t"]
+            "#,
+        )
+        .unwrap();
+        let conflicts = vec![(
+            "tgz".to_string(),
+            vec!["other".to_string(), "targz".to_string(), "tgz".to_string()],
+        )];
+        assert_eq!(conflicts, cfg.format_name_conflicts());
+    }
+
+    #[test]
+    fn format_merge_commands() {
+        let s = String::from;
+        let format = |paths: &[&str], strategy| Format {
+            decompress: Configure(Some(
+                paths.iter().map(|path| Command::new(*path, &[])).collect(),
+            )),
+            merge_commands: Configure(strategy),
+            ..Default::default()
+        };
+        let merged = |strategy, lower: &Format| {
+            let mut format = format(&["root"], strategy);
+            format.struct_merge(lower);
+            let paths = format.decompress.c().iter().map(|c| c.path.clone());
+            (paths.collect::<Vec<_>>(), format.merge_commands.0)
+        };
+        let default = format(&["default"], None);
+
+        let replaced = (vec![s("root")], Some(MergeStrategy::Replace));
+        assert_eq!(replaced, merged(Some(MergeStrategy::Replace), &default));
+        assert_eq!((vec![s("root")], None), merged(None, &default));
+        assert_eq!(
+            (vec![s("root"), s("default")], None),
+            merged(Some(MergeStrategy::Prepend), &default)
+        );
+        assert_eq!(
+            (vec![s("default"), s("root")], None),
+            merged(Some(MergeStrategy::Append), &default)
+        );
+        let lower = format(&["global"], Some(MergeStrategy::Append));
+        assert_eq!(
+            (vec![s("root"), s("global")], Some(MergeStrategy::Append)),
+            merged(Some(MergeStrategy::Prepend), &lower)
+        );
+        let (paths, _) =
+            merged(Some(MergeStrategy::Prepend), &Format::default());
+        assert_eq!(vec![s("root")], paths);
+    }
+
+    #[test]
+    fn configure_accessors() {
+        let set = Configure(Some(1));
+        let unset = Configure::<i32>(None);
+        assert_eq!(Some(&1), set.get());
+        assert_eq!(None, unset.get());
+        assert_eq!(&1, set.or(&2));
+        assert_eq!(&2, unset.or(&2));
+        assert_eq!(&1, set.c_or_err("a.b").unwrap());
+        let report = unset.c_or_err("a.b").unwrap_err();
+        assert_eq!("missing config field `a.b`", report.to_string());
+    }
+
+    #[test]
+    fn command_diagnostics() {
+        assert_eq!(
+            Vec::<CommandDiagnostic>::new(),
+            Cfg::default().command_diagnostics()
+        );
+
+        let cfg = toml::from_str::<Cfg>(
+            r#"
+            [[formats.zip.decompress]]
+            path = "unzip"
+            args = ["{File}", "-d", "{DIR}"]
+
+            [[formats.zip.decompress]]
+            path = "unzip"
+            args = ["-l"]
+            "#,
+        )
+        .unwrap();
+        let diagnostic = |index, kind| CommandDiagnostic {
+            format: "zip".to_string(),
+            list: "decompress",
+            index,
+            kind,
+        };
+        assert_eq!(
+            vec![
+                diagnostic(
+                    0,
+                    CommandDiagnosticKind::UnknownPlaceholder(
+                        "File".to_string()
+                    )
+                ),
+                diagnostic(1, CommandDiagnosticKind::NoPlaceholder),
+            ],
+            cfg.command_diagnostics()
+        );
+    }
+
+    #[test]
+    fn decompress_command_format() {
+        let command = Command {
+            path: "x".to_string(),
+            args: vec![
+                "{FILE}{DIR}".to_string(),
+                "{{FILE}}".to_string(),
+                "{UNKNOWN}".to_string(),
+            ],
+            ..Default::default()
+        };
+        let command = command
+            .decompress_command_format(&Substitutions {
+                file: "a.zip",
+                dir: "out",
+                ..Default::default()
+            })
+            .command;
+        assert_eq!(
+            vec!["a.zipout", "{FILE}", "{UNKNOWN}"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn decompress_command_format_basename() {
+        let command = Command {
+            path: "x".to_string(),
+            args: vec!["{BASENAME}".to_string()],
+            ..Default::default()
+        };
+        let command = command
+            .decompress_command_format(&Substitutions {
+                file: "/in/a.tar.gz",
+                dir: "out",
+                ..Default::default()
+            })
+            .command;
+        assert_eq!(vec!["a.tar"], command.get_args().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn decompress_command_format_cwd() {
+        let command = Command {
+            path: "x".to_string(),
+            cwd: Some("{DIR}/sub".to_string()),
+            ..Default::default()
+        };
+        let command = command
+            .decompress_command_format(&Substitutions {
+                file: "a.zip",
+                dir: "out",
+                ..Default::default()
+            })
+            .command;
+        assert_eq!(Some(Path::new("out/sub")), command.get_current_dir());
+
+        let command = Command::default();
+        let command = command
+            .decompress_command_format(&Substitutions {
+                file: "a.zip",
+                dir: "out",
+                ..Default::default()
+            })
+            .command;
+        assert_eq!(None, command.get_current_dir());
+    }
+
+    #[test]
+    fn decompress_command_format_password() {
+        let command = Command {
+            path: "7z".to_string(),
+            args: vec!["x".to_string(), "-p{PASSWORD}".to_string()],
+            ..Default::default()
+        };
+        let with = command
+            .decompress_command_format(&Substitutions {
+                file: "a.7z",
+                dir: "a",
+                password: Some("pw"),
+                ..Default::default()
+            })
+            .command;
+        assert_eq!(vec!["x", "-ppw"], with.get_args().collect::<Vec<_>>());
+        let without = command
+            .decompress_command_format(&Substitutions {
+                file: "a.7z",
+                dir: "a",
+                ..Default::default()
+            })
+            .command;
+        assert_eq!(
+            vec!["x", "-p{PASSWORD}"],
+            without.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn decompress_command_format_key() {
+        let command = Command {
+            path: "sh".to_string(),
+            args: vec!["{KEY_FILE}".to_string(), "-k{KEY}".to_string()],
+            ..Default::default()
+        };
+        let key = KeyFile {
+            path: "/keys/a.key".to_string(),
+            key: Some("s3cret".to_string()),
+        };
+        let formatted = command.decompress_command_format(&Substitutions {
+            file: "a.gpg",
+            dir: "a",
+            key: Some(&key),
+            ..Default::default()
+        });
+        assert_eq!(
+            vec!["/keys/a.key", "-ks3cret"],
+            formatted.command.get_args().collect::<Vec<_>>()
+        );
+        assert_eq!(vec![1], formatted.sensitive_args);
+
+        let format = |key_file: &str| Format {
+            key_file: Configure(Some(Some(PathBuf::from(key_file)))),
+            ..Default::default()
+        };
+        let root = Path::new("/root");
+        assert_eq!(
+            Some(PathBuf::from("/root/keys/a.key")),
+            format("keys/../keys/a.key").key_file_path(Some(root))
+        );
+        assert_eq!(
+            Some(PathBuf::from("/a.key")),
+            format("/a.key").key_file_path(Some(root))
+        );
+        assert_eq!(
+            Some(PathBuf::from("a.key")),
+            format("a.key").key_file_path(None)
+        );
+        assert_eq!(None, Format::default().key_file_path(Some(root)));
+    }
+
+    #[test]
+    fn decompress_command_format_directories() {
+        let command = Command {
+            path: "x".to_string(),
+            args: vec![
+                "{ROOT}".to_string(),
+                "{OUTPUT_DIR}/{BASENAME}".to_string(),
+                "{ARCHIVE_DIR}".to_string(),
+            ],
+            ..Default::default()
+        };
+        let substitutions = Substitutions {
+            file: "a.zip",
+            dir: "out",
+            root: Some(Path::new("/project")),
+            output_dir: Some(Path::new("/project/output")),
+            archive_dir: Some(Path::new("/project/archive")),
+            ..Default::default()
+        };
+        let formatted = command.decompress_command_format(&substitutions);
+        assert_eq!(
+            vec!["/project", "/project/output/a", "/project/archive"],
+            formatted.command.get_args().collect::<Vec<_>>()
+        );
+        let unknown = command
+            .decompress_command_format(&Substitutions {
+                root: None,
+                output_dir: None,
+                archive_dir: None,
+                ..substitutions
+            })
+            .command;
+        assert_eq!(
+            vec!["{ROOT}", "{OUTPUT_DIR}/a", "{ARCHIVE_DIR}"],
+            unknown.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn formatted_command_redacted() {
+        let command = Command {
+            path: "7z".to_string(),
+            args: vec![
+                "x".to_string(),
+                "-p{PASSWORD}".to_string(),
+                "{FILE}".to_string(),
+            ],
+            cwd: Some("{DIR}".to_string()),
+            env: HashMap::from_iter([
+                ("PW".to_string(), "{PASSWORD}".to_string()),
+                ("TOKEN".to_string(), "secret-token".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let formatted = command.decompress_command_format(&Substitutions {
+            file: "a.7z",
+            dir: "a",
+            password: Some("hunter2"),
+            ..Default::default()
+        });
+        assert_eq!(vec![1], formatted.sensitive_args);
+        assert_eq!(vec!["PW".to_string()], formatted.sensitive_env);
+        assert_eq!(
+            r#"cd "a" && PW=*** TOKEN=*** "7z" "x" *** "a.7z""#,
+            formatted.redacted(&["secret".to_string()])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn decompress_command_format_env() {
+        let command = Command {
+            path: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                r#"test "$CHEWWY_TEST" = "a.zip in out""#.to_string(),
+            ],
+            env: HashMap::from_iter([(
+                "CHEWWY_TEST".to_string(),
+                "{FILE} in {DIR}".to_string(),
+            )]),
+            ..Default::default()
+        };
+        let status = command
+            .decompress_command_format(&Substitutions {
+                file: "a.zip",
+                dir: "out",
+                ..Default::default()
+            })
+            .command
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn serialize_omits_unset() {
+        let cfg = toml::from_str::<Cfg>(
+            r#"
+            [formats.zip]
+            extensions = ["zip"]
+            "#,
+        )
+        .unwrap();
+        let serialized = toml::to_string(&cfg).unwrap();
+        assert!(!serialized.contains("priority"));
+        assert!(!serialized.contains("commands"));
+
+        let serialized = toml::to_string(&Cfg::default()).unwrap();
+        let cfg = toml::from_str::<Cfg>(&serialized).unwrap();
+        assert!(cfg.validate().is_ok());
+    }
+
+    #[test]
+    fn resolve_extends() {
+        let mut cfg = toml::from_str::<Cfg>(
+            r#"
+            [formats.myzip]
+            extends = "zip"
+            priority = 1
+
+            [[formats.myzip.decompress]]
+            path = "bsdtar"
+            args = ["-xf", "{FILE}", "-C", "{DIR}"]
+
+            [formats.myzip2]
+            extends = "myzip"
+            "#,
+        )
+        .unwrap();
+        cfg.struct_merge(&Cfg::default());
+        cfg.resolve_extends().unwrap();
+
+        let formats = cfg.formats.c();
+        assert!(formats.contains_key("zip"));
+        for format_name in ["myzip", "myzip2"] {
+            let format = &formats[format_name];
+            assert!(format.extensions.c().contains("zip"));
+            assert_eq!("bsdtar", format.decompress.c()[0].path);
+            assert_eq!(1, *format.priority.c());
+        }
+    }
+
+    #[test]
+    fn resolve_extends_error() {
+        let mut cfg = toml::from_str::<Cfg>(
+            r#"
+            [formats.a]
+            extends = "b"
+            [formats.b]
+            extends = "c"
+            [formats.c]
+            extends = "a"
+            "#,
+        )
+        .unwrap();
+        match cfg.resolve_extends() {
+            Err(ResolveExtendsError::Cycle(cycle)) => {
+                assert_eq!(vec!["a", "b", "c", "a"], cycle)
+            }
+            other => panic!("expected cycle, got {other:?}"),
+        }
+
+        let mut cfg =
+            toml::from_str::<Cfg>("[formats.a]\nextends = \"zip\"").unwrap();
+        assert!(matches!(
+            cfg.resolve_extends(),
+            Err(ResolveExtendsError::UnknownFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn merge_format_compress() {
+        let mut cfg = toml::from_str::<Cfg>(
+            r#"
+            [[formats.zip.compress]]
+            path = "zip"
+            args = ["-r", "{FILE}", "{DIR}"]
+            "#,
+        )
+        .unwrap();
+        cfg.struct_merge(&Cfg::default());
+
+        let zip = &cfg.formats.c()["zip"];
+        assert_eq!(1, zip.compress.c().len());
+        assert_eq!("zip", zip.compress.c()[0].path);
+        assert!(zip.extensions.c().contains("zip"));
+        assert!(!zip.decompress.c().is_empty());
+    }
+
+    #[test]
+    fn directories_to_absolute() {
+        let directories = Directories {
+            search: Configure(Some(Some(PathBuf::from("./a/../search")))),
+            output: Configure(Some(Some(PathBuf::from("../secrets")))),
+            archive: Configure(Some(None)),
+        };
+        let absolute = directories.to_absolute("/root/project");
+        assert_eq!(
+            Some(Path::new("/root/project/search")),
+            absolute.search.c().as_deref()
+        );
+        assert_eq!(
+            Some(Path::new("/root/secrets")),
+            absolute.output.c().as_deref()
+        );
+        assert_eq!(
+            Some(Path::new("/root/secrets")),
+            absolute.find_outside("/root/project")
+        );
+        assert_eq!(None, absolute.find_outside("/root"));
+    }
+
+    #[test]
+    fn directories_overlaps() {
+        let directories = |search: &str, output: &str, archive: &str| {
+            Directories {
+                search: Configure(Some(Some(PathBuf::from(search)))),
+                output: Configure(Some(Some(PathBuf::from(output)))),
+                archive: Configure(Some(Some(PathBuf::from(archive)))),
+            }
+            .to_absolute("/nonexistent/project")
+        };
+        assert!(directories("search", "output", "archive")
+            .overlaps()
+            .is_empty());
+
+        let overlaps = directories(".", "output", ".").overlaps();
+        let messages =
+            overlaps.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "output directory `/nonexistent/project/output` is inside \
+                 the search directory `/nonexistent/project`",
+                "search and archive directories are both \
+                 `/nonexistent/project`",
+                "output directory `/nonexistent/project/output` is inside \
+                 the archive directory `/nonexistent/project`",
+            ],
+            messages
+        );
+        assert_eq!(
+            vec![false, true, false],
+            overlaps.iter().map(|o| o.is_same()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn struct_merge_formats_union() {
+        let mut cfg = Cfg::from_toml_str(
+            r#"
+            [formats.custom]
+            extensions = ["custom"]
+            decompress = []
+            [formats.zip]
+            priority = 5
+            "#,
+        )
+        .unwrap();
+        cfg.struct_merge(&Cfg::default());
+        let formats = cfg.formats.c();
+        assert!(formats.contains_key("custom"));
+        assert!(formats.contains_key("rar"));
+        assert_eq!(&5, formats["zip"].priority.c());
+        assert!(formats["zip"].extensions.c().contains("zip"));
+    }
+
+    #[test]
+    fn from_toml_str() {
+        let mut cfg = Cfg::from_toml_str(
+            r#"
+            [options]
+            quiet = true
+            "#,
+        )
+        .unwrap();
+        assert!(cfg.formats.0.is_none());
+        cfg.insert_format(
+            "tar",
+            Format {
+                extensions: Configure(Some(HashSet::from(["tar".to_string()]))),
+                decompress: Configure(Some(vec![Command::new(
+                    "tar",
+                    &["-xf", "{FILE}", "-C", "{DIR}"],
+                )])),
+                ..Default::default()
+            },
+        );
+        cfg.struct_merge(&Cfg::default());
+        assert!(cfg.options.c().quiet.c());
+        assert!(cfg.formats.c().contains_key("tar"));
+        assert!(cfg.validate().is_ok());
+
+        assert!(matches!(
+            Cfg::from_toml_str("options = 1"),
+            Err(LoadCfgError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn load_cfg_with_warnings_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cfg.toml");
+        fs::write(
+            &path,
+            r#"
+            [options]
+            quiet = true
+            colour = "never"
+
+            [commands.manage.diretories]
+            search = "search"
+
+            [[formats.zip.decompress]]
+            path = "unzip"
+            args = []
+            arg = []
+            "#,
+        )
+        .unwrap();
+        let (cfg, unknown_keys) = load_cfg_with_warnings(&path).unwrap();
+        assert!(cfg.options.c().quiet.c());
+        assert_eq!(
+            vec![
+                "options.colour".to_string(),
+                "commands.manage.diretories".to_string(),
+                "formats.zip.decompress.0.arg".to_string(),
+            ],
+            unknown_keys
+        );
+    }
+
+    #[test]
+    fn load_cfg_error_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cfg.toml");
+        fs::write(&path, "[options]\nquiet = 1\n").unwrap();
+        let message = load_cfg(&path).unwrap_err().to_string();
+        assert!(message.contains(&path.display().to_string()), "{message}");
+        assert!(message.contains("line 2"), "{message}");
+
+        let missing = dir.path().join("missing.toml");
+        assert!(load_cfg_if_exists(&missing).unwrap().is_none());
+        let message = load_cfg(&missing).unwrap_err().to_string();
+        assert!(
+            message.contains(&missing.display().to_string()),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn validate() {
+        assert!(Cfg::default().validate().is_ok());
+
+        let cfg = toml::from_str::<Cfg>(
+            r#"
+            [commands.manage]
+            search-file = true
+
+            [commands.manage.directories]
+            search = "search"
+
+            [formats.zip]
+            extensions = ["zip"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Err(vec![
+                "formats.zip.decompress".to_string(),
+                "commands.manage.smart-decompress-directory".to_string(),
+                "commands.manage.smart-decompress-depth".to_string(),
+                "commands.manage.single-file-output".to_string(),
+                "commands.manage.empty-output".to_string(),
+                "commands.manage.search-ignore".to_string(),
+                "commands.manage.search-sort".to_string(),
+                "commands.manage.search-depth".to_string(),
+                "commands.manage.search-max-entries".to_string(),
+                "commands.manage.follow-symlinks".to_string(),
+                "commands.manage.output-file-action".to_string(),
+                "commands.manage.compressed-file-action".to_string(),
+                "commands.manage.output-dir-template".to_string(),
+                "commands.manage.create-missing-dirs".to_string(),
+                "commands.manage.archive-name-template".to_string(),
+                "commands.manage.archive-on-exists".to_string(),
+                "commands.manage.confine-directories".to_string(),
+                "commands.manage.post-decompress".to_string(),
+                "commands.manage.post-decompress-fatal".to_string(),
+                "commands.manage.verify-checksum".to_string(),
+                "commands.manage.require-checksum".to_string(),
+                "commands.manage.space-check".to_string(),
+                "commands.manage.space-ratio".to_string(),
+                "commands.manage.min-free-space".to_string(),
+                "commands.manage.write-manifest".to_string(),
+                "commands.manage.directories.output".to_string(),
+                "commands.manage.directories.archive".to_string(),
+                "commands.compress".to_string(),
+                "options".to_string(),
+            ]),
+            cfg.validate()
+        );
+    }
+}
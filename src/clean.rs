@@ -0,0 +1,382 @@
+use crate::prelude::*;
+use std::{
+    io::{IsTerminal, Write},
+    time,
+};
+
+use serde::Serialize;
+
+use crate::{
+    cfg::{self, Cfg, StructMerge},
+    manage, utils,
+};
+
+#[derive(Debug, Error)]
+#[error("clean error")]
+pub struct CleanError;
+
+#[derive(Debug, Default, Clone)]
+pub struct CleanOptions {
+    /// Also remove output directories not modified for this long, even if
+    /// their archive is still there
+    pub older_than: Option<time::Duration>,
+    /// Don't remove anything, only report what would be removed
+    pub dry_run: bool,
+    /// Remove without asking first
+    pub yes: bool,
+    /// Takes precedence over `commands.manage.directories`
+    pub directories: cfg::Directories,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CleanSummary {
+    /// Output directories removed, or that would be when `dry_run`
+    pub removed: Vec<PathBuf>,
+    pub dry_run: bool,
+}
+
+/// A directory in the output directory
+#[derive(Debug, Clone)]
+pub struct OutputEntry {
+    pub path: PathBuf,
+    pub modified: Option<time::SystemTime>,
+}
+
+/// The `output_dirs` to remove: the ones no archive of `archive_names`
+/// renders to with `output_dir_template`, and with `older_than` the ones
+/// not modified for that long. `{DATE}` and `{TIME}` match anything since
+/// they are of when the archive was managed
+pub fn clean_candidates<'a>(
+    output_dirs: &'a [OutputEntry],
+    archive_names: &[String],
+    output_dir_template: &str,
+    older_than: Option<time::Duration>,
+    now: time::SystemTime,
+) -> Vec<&'a OutputEntry> {
+    let patterns = archive_names
+        .iter()
+        .filter_map(|name| output_dir_pattern(output_dir_template, name))
+        .collect::<Vec<_>>();
+    output_dirs
+        .iter()
+        .filter(|entry| {
+            let name = entry.path.file_name().unwrap_or_default();
+            let name = name.to_string_lossy();
+            let orphaned = !patterns.iter().any(|p| p.matches(&name));
+            let old = older_than.is_some_and(|older_than| {
+                entry
+                    .modified
+                    .and_then(|modified| now.duration_since(modified).ok())
+                    .is_some_and(|age| age > older_than)
+            });
+            orphaned || old
+        })
+        .collect()
+}
+
+/// Matches the names `output-dir-template` gives the output directory of
+/// `archive_name`
+fn output_dir_pattern(
+    template: &str,
+    archive_name: &str,
+) -> Option<glob::Pattern> {
+    let file_name = Path::new(archive_name);
+    let name = file_name.with_extension("");
+    let ext = file_name.extension().unwrap_or_default();
+    let mut pattern = String::new();
+    for token in utils::template_tokens(template) {
+        match token {
+            utils::TemplateToken::Text(text) => {
+                pattern.push_str(&glob::Pattern::escape(text))
+            }
+            utils::TemplateToken::Placeholder("NAME") => pattern
+                .push_str(&glob::Pattern::escape(&name.to_string_lossy())),
+            utils::TemplateToken::Placeholder("EXT") => {
+                pattern.push_str(&glob::Pattern::escape(&ext.to_string_lossy()))
+            }
+            utils::TemplateToken::Placeholder("FILE_NAME") => {
+                pattern.push_str(&glob::Pattern::escape(archive_name))
+            }
+            utils::TemplateToken::Placeholder(_) => pattern.push('*'),
+        }
+    }
+    glob::Pattern::new(&pattern).ok()
+}
+
+/// Remove the output directories whose archive is gone. Archives are
+/// looked for by name in the search directory and where the
+/// `compressed-file-action` moves them
+pub fn run(
+    cfg: &Cfg,
+    chewwy_root: Option<&Path>,
+    options: CleanOptions,
+) -> StackResult<CleanSummary, CleanError> {
+    let Some(chewwy_root) = chewwy_root else {
+        return Err(crate::NoChewwyRoot).change_context(CleanError);
+    };
+    let manage_cfg = cfg.commands.c().manage.c();
+    let messages = utils::Messages::new(*cfg.options.c().quiet.c());
+    let mut directories = options.directories;
+    directories.struct_merge(manage_cfg.directories.c());
+    let directories = directories.to_absolute(chewwy_root);
+    let Some(output_dir) = directories.output.c() else {
+        return Err(CleanError)
+            .attach_printable("`output` directory is not configured");
+    };
+    let mut summary = CleanSummary {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+    let output_dir = match output_dir.canonicalize() {
+        Ok(output_dir) => output_dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(summary),
+        Err(e) => {
+            return Err(e).change_context(CleanError).attach_printable_lazy(
+                || format!("can't resolve `{}`", output_dir.display()),
+            )
+        }
+    };
+    let output_dir_template = manage_cfg.output_dir_template.c();
+    if let Some(name) =
+        manage::unknown_name_placeholders(output_dir_template).first()
+    {
+        return Err(CleanError).attach_printable(format!(
+            "unknown placeholder `{{{name}}}` in `output-dir-template`"
+        ));
+    }
+    if manage_cfg.archive_name_template.c() != "{FILE_NAME}" {
+        return Err(CleanError).attach_printable(
+            "can't tell the output directory of archives renamed by \
+             `archive-name-template`",
+        );
+    }
+
+    let mut archive_dirs = vec![];
+    archive_dirs.extend(directories.search.c().clone());
+    match manage_cfg.compressed_file_action.c() {
+        cfg::CompressedFileAction::MoveToArchiveDir => {
+            archive_dirs.extend(directories.archive.c().clone())
+        }
+        cfg::CompressedFileAction::MoveToDir { path } => {
+            archive_dirs.push(chewwy_root.join(path))
+        }
+        cfg::CompressedFileAction::DoNothing => {}
+    }
+    let mut archive_names = vec![];
+    for dir in &archive_dirs {
+        archive_names.extend(file_names(dir)?);
+    }
+    // removing these would lose more than extracted content
+    let protected = archive_dirs
+        .iter()
+        .chain(directories.archive.c())
+        .chain([&chewwy_root.join(crate::DOT_DIR)])
+        .filter_map(|dir| dir.canonicalize().ok())
+        .collect::<Vec<_>>();
+
+    let output_dirs = fs::read_dir(&output_dir)
+        .change_context(CleanError)
+        .attach_printable_lazy(|| {
+            format!("can't read `{}`", output_dir.display())
+        })?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            // a symlink is not followed out of the output directory
+            let metadata = entry.path().symlink_metadata().ok()?;
+            metadata.is_dir().then(|| OutputEntry {
+                path: entry.path(),
+                modified: metadata.modified().ok(),
+            })
+        })
+        .filter(|entry| {
+            !protected.iter().any(|dir| dir.starts_with(&entry.path))
+        })
+        .collect::<Vec<_>>();
+    let mut candidates = clean_candidates(
+        &output_dirs,
+        &archive_names,
+        output_dir_template,
+        options.older_than,
+        time::SystemTime::now(),
+    );
+    candidates.sort_by(|a, b| a.path.cmp(&b.path));
+    summary.removed = candidates
+        .into_iter()
+        .map(|entry| entry.path.clone())
+        .collect();
+    if summary.removed.is_empty() || options.dry_run {
+        return Ok(summary);
+    }
+
+    if !options.yes {
+        confirm(&summary.removed, messages)?;
+    }
+    for dir in &summary.removed {
+        if dir.parent() != Some(output_dir.as_path()) {
+            return Err(CleanError).attach_printable_lazy(|| {
+                format!("`{}` is outside the output directory", dir.display())
+            });
+        }
+        log::info!("removing `{}`", dir.display());
+        fs::remove_dir_all(dir)
+            .change_context(CleanError)
+            .attach_printable_lazy(|| {
+                format!("can't remove `{}`", dir.display())
+            })?;
+    }
+    Ok(summary)
+}
+
+/// Names of the files in `dir`, none if it doesn't exist
+fn file_names(dir: &Path) -> StackResult<Vec<String>, CleanError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => {
+            return Err(e).change_context(CleanError).attach_printable_lazy(
+                || format!("can't read `{}`", dir.display()),
+            )
+        }
+    };
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect())
+}
+
+fn confirm(
+    dirs: &[PathBuf],
+    messages: utils::Messages,
+) -> StackResult<(), CleanError> {
+    if !io::stdin().is_terminal() {
+        return Err(CleanError)
+            .attach_printable("pass `--yes` to remove without asking");
+    }
+    // the directories are still listed when quiet, they're needed to answer
+    for dir in dirs {
+        messages.print(format_args!("{}\n", dir.display()));
+    }
+    messages.print(format_args!("Remove {} directories? [y/N] ", dirs.len()));
+    io::stdout()
+        .flush()
+        .change_context(CleanError)
+        .attach_printable("error flushing")?;
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .change_context(CleanError)?;
+    if !matches!(input.trim(), "y" | "Y" | "yes") {
+        return Err(error_stack::Report::new(manage::Cancelled))
+            .change_context(CleanError)
+            .attach_printable("nothing removed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clean_candidates_orphaned_and_old() {
+        let now = time::UNIX_EPOCH + time::Duration::from_secs(100 * 86400);
+        let day = time::Duration::from_secs(86400);
+        let entry = |name: &str, age_days: u32| OutputEntry {
+            path: Path::new("output").join(name),
+            modified: Some(now - day * age_days),
+        };
+        let output_dirs = [
+            entry("a", 1),
+            entry("b", 1),
+            entry("c.tar", 30),
+            entry("d [1]", 1),
+        ];
+        let archive_names =
+            ["a.zip", "c.tar.gz", "d [1].rar"].map(String::from);
+        let names = |template, older_than| {
+            clean_candidates(
+                &output_dirs,
+                &archive_names,
+                template,
+                older_than,
+                now,
+            )
+            .into_iter()
+            .map(|entry| entry.path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["b"], names("{NAME}", None));
+        assert_eq!(vec!["b", "c.tar"], names("{NAME}", Some(day * 7)));
+        assert_eq!(
+            vec!["a", "b", "c.tar", "d [1]"],
+            names("{NAME}-{DATE}", None)
+        );
+
+        let output_dirs = [entry("a-2024-01-01", 1), entry("b-2024-01-01", 1)];
+        let names = clean_candidates(
+            &output_dirs,
+            &archive_names,
+            "{NAME}-{DATE}",
+            None,
+            now,
+        );
+        assert_eq!(1, names.len());
+        assert!(names[0].path.ends_with("b-2024-01-01"));
+    }
+
+    #[test]
+    fn run_keeps_dirs_with_archives() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        for dir in ["output/a", "output/b", "archive", "search"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("archive/a.zip"), "").unwrap();
+        fs::write(root.join("output/file"), "").unwrap();
+        let options = CleanOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let summary =
+            run(&Cfg::default(), Some(&root), options.clone()).unwrap();
+        assert_eq!(vec![root.join("output/b")], summary.removed);
+        assert!(root.join("output/b").is_dir());
+
+        let options = CleanOptions {
+            dry_run: false,
+            yes: true,
+            ..options
+        };
+        let summary = run(&Cfg::default(), Some(&root), options).unwrap();
+        assert_eq!(vec![root.join("output/b")], summary.removed);
+        assert!(!root.join("output/b").exists());
+        assert!(root.join("output/a").is_dir());
+        assert!(root.join("output/file").is_file());
+    }
+
+    #[test]
+    fn run_never_removes_the_other_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("search")).unwrap();
+        fs::create_dir_all(root.join("archive")).unwrap();
+        fs::create_dir_all(root.join(crate::DOT_DIR)).unwrap();
+        fs::create_dir_all(root.join("orphan")).unwrap();
+        // the output directory is the chewwy root itself
+        let options = CleanOptions {
+            dry_run: true,
+            directories: cfg::Directories {
+                output: cfg::Configure(Some(Some(PathBuf::from(".")))),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let summary = run(&Cfg::default(), Some(&root), options).unwrap();
+        assert_eq!(vec![root.join("orphan")], summary.removed);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::prelude::*;
+use std::io::IsTerminal;
+
+use serde::Serialize;
+
+use crate::{
+    cfg::{self, Cfg},
+    file_archiver::FileArchiver,
+};
+
+#[derive(Debug, Error)]
+#[error("compress error")]
+pub struct CompressError;
+
+#[derive(Debug, Default, Clone)]
+pub struct CompressOptions {
+    pub dir: PathBuf,
+    /// Takes precedence over `commands.compress.format`
+    pub format: Option<String>,
+    /// Takes precedence over `commands.compress.source-dir-action`
+    pub source_dir_action: Option<cfg::SourceDirAction>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CompressSummary {
+    pub format: String,
+    pub archive: PathBuf,
+    /// Where the source directory was moved to
+    pub moved_source_dir: Option<PathBuf>,
+    pub deleted_source_dir: bool,
+}
+
+pub fn run(
+    cfg: &Cfg,
+    chewwy_root: Option<&Path>,
+    options: CompressOptions,
+) -> StackResult<CompressSummary, CompressError> {
+    let compress_cfg = cfg.commands.c().compress.c();
+    let quiet = *cfg.options.c().quiet.c();
+    let format_name = options
+        .format
+        .unwrap_or_else(|| compress_cfg.format.c().clone());
+    let dir = path::absolute(&options.dir)
+        .change_context(CompressError)
+        .attach_printable("cannot make path absolute")?;
+    if !dir.is_dir() {
+        return Err(CompressError).attach_printable_lazy(|| {
+            format!("`{}` is not a directory", options.dir.display())
+        });
+    }
+
+    let archive = FileArchiver::new(cfg.formats.c())
+        .chewwy_root(chewwy_root)
+        .progress(!quiet && io::stderr().is_terminal())
+        .redact(cfg.options.c().redact.c().clone())
+        .compress_dir(&dir, &format_name)
+        .change_context(CompressError)
+        .attach_printable_lazy(|| {
+            format!("cannot compress `{}`", dir.display())
+        })?;
+    let mut summary = CompressSummary {
+        format: format_name,
+        archive,
+        ..Default::default()
+    };
+
+    let source_dir_action = options
+        .source_dir_action
+        .unwrap_or_else(|| compress_cfg.source_dir_action.c().clone());
+    match source_dir_action {
+        cfg::SourceDirAction::DoNothing => {}
+        cfg::SourceDirAction::Delete => {
+            verify_archive(&summary.archive)?;
+            log::info!("deleting `{}`", dir.display());
+            fs::remove_dir_all(&dir)
+                .change_context(CompressError)
+                .attach_printable_lazy(|| {
+                    format!("can't delete `{}`", dir.display())
+                })?;
+            summary.deleted_source_dir = true;
+        }
+        cfg::SourceDirAction::MoveToDir { path } => {
+            let to_dir = match chewwy_root {
+                Some(chewwy_root) => chewwy_root.join(path),
+                None => path::absolute(&path)
+                    .change_context(CompressError)
+                    .attach_printable("cannot make path absolute")?,
+            };
+            let new_path = to_dir.join(dir.file_name().expect("dir name"));
+            fs::create_dir_all(&to_dir)
+                .change_context(CompressError)
+                .attach_printable_lazy(|| {
+                    format!("can't create directory `{}`", to_dir.display())
+                })?;
+            log::info!(
+                "moving `{}` to `{}`",
+                dir.display(),
+                new_path.display()
+            );
+            fs::rename(&dir, &new_path)
+                .change_context(CompressError)
+                .attach_printable_lazy(|| {
+                    format!("can't move source dir to `{}`", to_dir.display())
+                })?;
+            summary.moved_source_dir = Some(new_path);
+        }
+    }
+
+    Ok(summary)
+}
+
+/// The archive must exist and not be empty before its source is deleted
+fn verify_archive(archive: &Path) -> StackResult<(), CompressError> {
+    let metadata = fs::metadata(archive)
+        .change_context(CompressError)
+        .attach_printable_lazy(|| {
+            format!(
+                "`{}` was not created, keeping the source directory",
+                archive.display()
+            )
+        })?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return Err(CompressError).attach_printable_lazy(|| {
+            format!(
+                "`{}` is empty, keeping the source directory",
+                archive.display()
+            )
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cfg::StructMerge;
+
+    #[cfg(unix)]
+    #[test]
+    fn run_delete_source_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("mydir");
+        fs::create_dir(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        let mut cfg = toml::from_str::<Cfg>(
+            r#"
+            [formats.copy]
+            extensions = ["copy"]
+            decompress = []
+            [[formats.copy.compress]]
+            path = "cp"
+            args = ["{DIR}/a.txt", "{FILE}"]
+
+            [formats.empty]
+            extensions = ["empty"]
+            decompress = []
+            [[formats.empty.compress]]
+            path = "touch"
+            args = ["{FILE}"]
+            "#,
+        )
+        .unwrap();
+        cfg.struct_merge(&Cfg::default());
+        let options = |format: &str| CompressOptions {
+            dir: dir.clone(),
+            format: Some(format.to_string()),
+            source_dir_action: Some(cfg::SourceDirAction::Delete),
+        };
+
+        assert!(run(&cfg, Some(root.path()), options("empty")).is_err());
+        assert!(dir.is_dir());
+
+        let summary = run(&cfg, Some(root.path()), options("copy")).unwrap();
+        assert_eq!(root.path().join("mydir.copy"), summary.archive);
+        assert!(summary.deleted_source_dir);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::prelude::*;
+
+use serde::Serialize;
+
+use crate::{cfg::Cfg, utils};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "OK"),
+            CheckStatus::Warn => write!(f, "WARN"),
+            CheckStatus::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Check {
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.status, self.message)
+    }
+}
+
+impl Check {
+    fn new(status: CheckStatus, message: String) -> Check {
+        Check { status, message }
+    }
+}
+
+/// Check the setup in order: chewwy root, config, configured directories,
+/// then decompress commands of every format against `path_var` (`PATH`).
+/// `cfg` is the merged config or why it couldn't be loaded
+pub fn diagnose(
+    chewwy_root: Option<&Path>,
+    cfg: Result<&Cfg, String>,
+    path_var: Option<&OsStr>,
+) -> Vec<Check> {
+    let mut checks = vec![];
+    match chewwy_root {
+        Some(root) => checks.push(Check::new(
+            CheckStatus::Ok,
+            format!("chewwy root found at `{}`", root.display()),
+        )),
+        None => checks.push(Check::new(
+            CheckStatus::Warn,
+            format!(
+                "no chewwy root found, create a `{}` directory",
+                crate::DOT_DIR
+            ),
+        )),
+    }
+
+    let cfg = match cfg {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            checks.push(Check::new(
+                CheckStatus::Fail,
+                format!("config is invalid: {e}"),
+            ));
+            return checks;
+        }
+    };
+    match cfg.validate() {
+        Ok(()) => {
+            checks.push(Check::new(CheckStatus::Ok, "config is valid".into()))
+        }
+        Err(missing) => {
+            for path in missing {
+                checks.push(Check::new(
+                    CheckStatus::Fail,
+                    format!("missing config `{path}`"),
+                ));
+            }
+            return checks;
+        }
+    }
+
+    if let Some(root) = chewwy_root {
+        let directories = cfg
+            .commands
+            .c()
+            .manage
+            .c()
+            .directories
+            .c()
+            .to_absolute(root);
+        for (name, dir) in [
+            ("search", &directories.search),
+            ("output", &directories.output),
+            ("archive", &directories.archive),
+        ] {
+            let Some(dir) = dir.c() else {
+                checks.push(Check::new(
+                    CheckStatus::Warn,
+                    format!("{name} directory is not configured"),
+                ));
+                continue;
+            };
+            if dir.is_dir() {
+                checks.push(Check::new(
+                    CheckStatus::Ok,
+                    format!("{name} directory `{}` exists", dir.display()),
+                ));
+            } else {
+                checks.push(Check::new(
+                    CheckStatus::Warn,
+                    format!("{name} directory `{}` is missing", dir.display()),
+                ));
+            }
+        }
+        for overlap in directories.overlaps() {
+            let status = if overlap.is_same() {
+                CheckStatus::Fail
+            } else {
+                CheckStatus::Warn
+            };
+            checks.push(Check::new(status, overlap.to_string()));
+        }
+    }
+
+    let formats = cfg.formats.c();
+    let mut format_names = formats.keys().collect::<Vec<_>>();
+    format_names.sort();
+    for format_name in format_names {
+        let commands = formats[format_name].decompress.c();
+        let found = commands.iter().find(|command| {
+            path_var
+                .and_then(|path_var| {
+                    utils::find_executable(&command.path, path_var)
+                })
+                .is_some()
+        });
+        match found {
+            Some(command) => checks.push(Check::new(
+                CheckStatus::Ok,
+                format!("format {format_name} can use `{}`", command.path),
+            )),
+            None => checks.push(Check::new(
+                CheckStatus::Warn,
+                format!(
+                    "format {format_name} has none of {:?} on PATH",
+                    commands
+                        .iter()
+                        .map(|command| command.path.as_str())
+                        .collect::<Vec<_>>()
+                ),
+            )),
+        }
+    }
+    checks
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diagnose_checklist() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        fs::create_dir(root.join("search")).unwrap();
+        let bin = root.join("bin");
+        fs::create_dir(&bin).unwrap();
+        let unzip = bin.join("unzip");
+        fs::write(&unzip, "").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&unzip, fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+        let mut cfg = toml::from_str::<Cfg>(
+            r#"
+            [formats.zip]
+            extensions = ["zip"]
+            [[formats.zip.decompress]]
+            path = "7z"
+            args = []
+            [[formats.zip.decompress]]
+            path = "unzip"
+            args = []
+
+            [formats.rar]
+            extensions = ["rar"]
+            [[formats.rar.decompress]]
+            path = "unrar"
+            args = []
+            "#,
+        )
+        .unwrap();
+        let default = Cfg::default();
+        cfg.options = default.options;
+        cfg.commands = default.commands;
+
+        let checks = diagnose(Some(root), Ok(&cfg), Some(bin.as_os_str()))
+            .iter()
+            .map(|check| check.to_string())
+            .collect::<Vec<_>>();
+        let root = root.display();
+        assert_eq!(
+            vec![
+                format!("[OK] chewwy root found at `{root}`"),
+                "[OK] config is valid".to_string(),
+                format!("[OK] search directory `{root}/search` exists"),
+                format!("[WARN] output directory `{root}/output` is missing"),
+                format!("[WARN] archive directory `{root}/archive` is missing"),
+                "[WARN] format rar has none of [\"unrar\"] on PATH".to_string(),
+                "[OK] format zip can use `unzip`".to_string(),
+            ],
+            checks
+        );
+
+        let checks = diagnose(None, Err("bad".to_string()), None);
+        assert_eq!(
+            vec![CheckStatus::Warn, CheckStatus::Fail],
+            checks.iter().map(|check| check.status).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,159 +1,996 @@
 use crate::prelude::*;
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
-use crate::cfg;
-use cfg::Format;
+use crate::{cfg, interrupt};
+use cfg::{Format, Substitutions};
+
+#[derive(Debug, Error)]
+pub enum FindFormatError {
+    #[error(
+        "extension \"{extension}\" is claimed by multiple formats {formats:?}"
+    )]
+    AmbiguousExtension {
+        extension: String,
+        formats: Vec<String>,
+    },
+    #[error(
+        "MIME type \"{mime_type}\" is claimed by multiple formats {formats:?}"
+    )]
+    AmbiguousMimeType {
+        mime_type: String,
+        formats: Vec<String>,
+    },
+}
 
 #[derive(Debug, Error)]
 pub enum DecompressError {
     #[error("no format available for \"{file}\"")]
     NoFormatAvailable { file: String },
-    #[error("found format \"{found_format_name}\" for file \"{file}\" but no command available")]
+    #[error("{0}")]
+    FindFormat(FindFormatError),
+    #[error("found format \"{found_format_name}\" for file \"{file}\" but no command available, tried {attempted:?}")]
     NoCommandAvailable {
         file: String,
         found_format_name: String,
+        /// Executables that were not found
+        attempted: Vec<String>,
+    },
+    #[error("found format \"{found_format_name}\" for file \"{file}\" but it has no `decompress-stdout` command")]
+    NoStdoutCommand {
+        file: String,
+        found_format_name: String,
     },
-    #[error("error {io} trying to run the commannd {command_str} from command config {command:?} in format {format}")]
+    #[error("format \"{format}\" has no `decompress-pipe` command")]
+    NoPipeCommand { format: String },
+    #[error("found format \"{found_format_name}\" for file \"{file}\" but it has no `list` command")]
+    NoListCommand {
+        file: String,
+        found_format_name: String,
+    },
+    #[error("found format \"{found_format_name}\" for file \"{file}\" but it has no `verify` command")]
+    NoVerifyCommand {
+        file: String,
+        found_format_name: String,
+    },
+    #[error("no format named \"{format}\"")]
+    UnknownFormat { format: String },
+    #[error("format \"{format}\" has no `compress` command")]
+    NoCompressCommand { format: String },
+    #[error("format \"{format}\" has no extension to name the archive \"{dir}\" with")]
+    NoArchiveExtension { dir: String, format: String },
+    #[error("archive \"{archive}\" already exists")]
+    ArchiveExists { archive: String },
+    #[error("error {io} trying to run the commannd {command_str} in format {format}")]
     RunCommandError {
         command_str: String,
-        command: cfg::Command,
+        command: Box<cfg::Command>,
         io: io::Error,
         format: String,
     },
-    #[error("error code {code} from commannd {command_str} from command config {command:?} in format {format}")]
+    #[error(
+        "error code {code} from commannd {command_str} in format {format}"
+    )]
     ChildReturnErrorCode {
         command_str: String,
-        command: cfg::Command,
+        command: Box<cfg::Command>,
         code: i32,
         format: String,
     },
-    #[error("error return from commannd {command_str} from command config {command:?} in format {format}")]
+    #[error("error return from commannd {command_str} in format {format}")]
     ChildError {
         command_str: String,
-        command: cfg::Command,
+        command: Box<cfg::Command>,
         format: String,
     },
-    #[error("error {io} return from commannd {command_str} from command config {command:?} in format {format}")]
+    #[error(
+        "error {io} return from commannd {command_str} in format {format}"
+    )]
     ChildWaitReturnError {
         command_str: String,
-        command: cfg::Command,
+        command: Box<cfg::Command>,
+        format: String,
+        io: io::Error,
+    },
+    #[error("can't read the key file \"{path}\" of format {format}: {io}")]
+    KeyFile {
+        path: String,
         format: String,
         io: io::Error,
     },
+    #[error("can't strip the leading directories of \"{dir}\" {error}")]
+    StripComponents {
+        dir: String,
+        error: crate::utils::UnnestDirError,
+    },
+    #[error("cancelled by user while running a command of format {format}")]
+    Interrupted { format: String },
+    #[error("every command of format {format} failed for \"{file}\": {}", failures.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))]
+    AllCommandsFailed {
+        file: String,
+        format: String,
+        failures: Vec<DecompressError>,
+    },
+}
+
+impl DecompressError {
+    /// No configured command of the format could be found to run
+    pub fn is_missing_command(&self) -> bool {
+        matches!(self, DecompressError::NoCommandAvailable { .. })
+    }
+
+    /// Guess from the exit code whether the command failed because the
+    /// archive is damaged or not of its format. `2`, `3` and `9` are what
+    /// `7z`, `unzip`, `unrar` and `gzip` use for these
+    pub fn is_archive_corrupt(&self) -> bool {
+        match self {
+            DecompressError::ChildReturnErrorCode { code, .. } => {
+                matches!(code, 2 | 3 | 9)
+            }
+            DecompressError::AllCommandsFailed { failures, .. } => {
+                failures.iter().all(DecompressError::is_archive_corrupt)
+            }
+            _ => false,
+        }
+    }
+
+    /// The config is missing something the action needs, it fails the
+    /// same way for every file
+    pub fn is_config_problem(&self) -> bool {
+        matches!(
+            self,
+            DecompressError::FindFormat(_)
+                | DecompressError::NoStdoutCommand { .. }
+                | DecompressError::NoPipeCommand { .. }
+                | DecompressError::NoListCommand { .. }
+                | DecompressError::NoVerifyCommand { .. }
+                | DecompressError::KeyFile { .. }
+                | DecompressError::UnknownFormat { .. }
+                | DecompressError::NoCompressCommand { .. }
+                | DecompressError::NoArchiveExtension { .. }
+        )
+    }
+
+    /// The error is specific to this file, so other files may still
+    /// succeed
+    pub fn is_recoverable(&self) -> bool {
+        !self.is_config_problem() && !self.is_missing_command()
+    }
+}
+
+/// Spawns the commands of a `FileArchiver` and waits for them. Replaced by
+/// a fake in tests so they don't depend on the installed tools
+pub trait CommandRunner: fmt::Debug + Send + Sync {
+    /// Run `command` to its end. A program that doesn't exist is a
+    /// `RunError::Spawn` of kind `NotFound`
+    fn run(
+        &self,
+        command: &mut process::Command,
+        options: RunOptions,
+    ) -> Result<process::ExitStatus, RunError>;
+}
+
+/// How a `CommandRunner` handles the output of a command
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunOptions {
+    /// Show a spinner on stderr while waiting
+    pub progress: bool,
+    /// Print the output at once when the command is done
+    pub buffer_output: bool,
+    pub stdout_to_stderr: bool,
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    Spawn(io::Error),
+    Wait(io::Error),
+    /// Ctrl-C was pressed, the command was stopped or not started
+    Interrupted,
+}
+
+/// Runs commands as child processes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn run(
+        &self,
+        command: &mut process::Command,
+        options: RunOptions,
+    ) -> Result<process::ExitStatus, RunError> {
+        if options.buffer_output {
+            command
+                .stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped());
+        } else if options.stdout_to_stderr {
+            command.stdout(io::stderr());
+        }
+        if interrupt::interrupted() {
+            return Err(RunError::Interrupted);
+        }
+        let _running = interrupt::Running::start();
+        let mut child = command.spawn().map_err(RunError::Spawn)?;
+        let status = if options.buffer_output {
+            wait_buffered(
+                child,
+                options.stdout_to_stderr,
+                interrupt::interrupted,
+            )
+        } else {
+            wait_with_progress(
+                &mut child,
+                options.progress,
+                interrupt::interrupted,
+            )
+        };
+        let status = status.map_err(RunError::Wait)?;
+        if interrupt::interrupted() {
+            return Err(RunError::Interrupted);
+        }
+        Ok(status)
+    }
 }
 
 pub struct FileArchiver<'cfg> {
     formats: &'cfg HashMap<String, Format>,
+    runner: Arc<dyn CommandRunner>,
+    progress: bool,
+    buffer_output: bool,
+    stdout_to_stderr: bool,
+    case_sensitive_extensions: bool,
+    password: Option<String>,
+    chewwy_root: Option<PathBuf>,
+    output_dir: Option<PathBuf>,
+    archive_dir: Option<PathBuf>,
+    redact: Vec<String>,
 }
 
 impl<'cfg> FileArchiver<'cfg> {
     pub fn new(formats: &'cfg HashMap<String, Format>) -> Self {
-        FileArchiver { formats }
+        FileArchiver {
+            formats,
+            runner: Arc::new(ProcessRunner),
+            progress: false,
+            buffer_output: false,
+            stdout_to_stderr: false,
+            case_sensitive_extensions: false,
+            password: None,
+            chewwy_root: None,
+            output_dir: None,
+            archive_dir: None,
+            redact: vec![],
+        }
+    }
+
+    /// Where relative `key-file`s of formats are resolved from, instead of
+    /// the current directory
+    pub fn chewwy_root(mut self, chewwy_root: Option<&Path>) -> Self {
+        self.chewwy_root = chewwy_root.map(Path::to_path_buf);
+        self
+    }
+
+    /// The resolved `output` and `archive` directories, substituted for
+    /// `{OUTPUT_DIR}` and `{ARCHIVE_DIR}`
+    pub fn directories(mut self, directories: &cfg::Directories) -> Self {
+        self.output_dir = directories.output.0.clone().flatten();
+        self.archive_dir = directories.archive.0.clone().flatten();
+        self
+    }
+
+    /// Run commands with `runner` instead of spawning them
+    pub fn runner(mut self, runner: Arc<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Show a spinner on stderr while waiting for a slow command
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Match file extensions to format extensions exactly instead of
+    /// ignoring case
+    pub fn case_sensitive_extensions(
+        mut self,
+        case_sensitive_extensions: bool,
+    ) -> Self {
+        self.case_sensitive_extensions = case_sensitive_extensions;
+        self
+    }
+
+    /// Collect the output of commands and print it at once when they
+    /// finish, so commands running in parallel don't interleave
+    pub fn buffer_output(mut self, buffer_output: bool) -> Self {
+        self.buffer_output = buffer_output;
+        self
+    }
+
+    /// Send the stdout of commands to stderr, leaving stdout to the output
+    /// read by other programs
+    pub fn stdout_to_stderr(mut self, stdout_to_stderr: bool) -> Self {
+        self.stdout_to_stderr = stdout_to_stderr;
+        self
+    }
+
+    /// Substituted for `{PASSWORD}` in commands, and hidden from logs and
+    /// errors showing the command
+    pub fn password(mut self, password: Option<String>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// Hide command args and environment values containing any of
+    /// `redact` from logs and errors showing the command
+    pub fn redact(mut self, redact: Vec<String>) -> Self {
+        self.redact = redact;
+        self
     }
 
+    /// Returns the format and command used
     pub fn decompress_to_dir<F, D>(
         &self,
         file: F,
         dir: D,
-    ) -> Result<(), DecompressError>
+    ) -> Result<Decompressed, DecompressError>
+    where
+        F: AsRef<Path>,
+        D: AsRef<Path>,
+    {
+        let file_str = file.as_ref().to_string_lossy();
+        let (format_name, format) = self.require_format(&file_str)?;
+        self.decompress_to_dir_as(&file_str, dir.as_ref(), format_name, format)
+    }
+
+    /// Like [`FileArchiver::decompress_to_dir`] with the format named
+    /// `format_name` instead of the one matching the file
+    pub fn decompress_to_dir_with_format<F, D>(
+        &self,
+        file: F,
+        dir: D,
+        format_name: &str,
+    ) -> Result<Decompressed, DecompressError>
     where
         F: AsRef<Path>,
         D: AsRef<Path>,
     {
         let file_str = file.as_ref().to_string_lossy();
-        let dir_str = dir.as_ref().to_string_lossy();
-        let Some((format_name, format)) = self.find_format(&file) else {
+        let (format_name, format) = self.named_format(format_name)?;
+        self.decompress_to_dir_as(&file_str, dir.as_ref(), format_name, format)
+    }
+
+    fn decompress_to_dir_as(
+        &self,
+        file_str: &str,
+        dir: &Path,
+        format_name: &str,
+        format: &Format,
+    ) -> Result<Decompressed, DecompressError> {
+        let dir_str = dir.to_string_lossy();
+        let dir_existed = dir.symlink_metadata().is_ok();
+        // don't let the next command extract over partial output
+        let retry_cleanup = || {
+            if dir_existed {
+                log::warn!(
+                    "`{}` may contain partial output of the failed command",
+                    dir.display()
+                );
+            } else if dir.symlink_metadata().is_ok() {
+                if let Err(e) = fs::remove_dir_all(dir) {
+                    log::warn!(
+                        "can't remove partial output `{}`: {e}",
+                        dir.display()
+                    );
+                }
+            }
+        };
+        let try_next_on_failure = format.try_next_on_failure.0.unwrap_or(false);
+        let strip_components = format.strip_components.0;
+        let key = self.key_file(format_name, format, format.decompress.c())?;
+        let build = |command: &cfg::Command| {
+            command.decompress_command_format(&Substitutions {
+                strip_components,
+                ..self.substitutions(file_str, &dir_str, key.as_ref())
+            })
+        };
+        let command = self.run_first_available(
+            file_str,
+            format_name,
+            format.decompress.c(),
+            build,
+            try_next_on_failure.then_some(&retry_cleanup as &dyn Fn()),
+        )?;
+        let strips_natively =
+            command.placeholders().any(|name| name == "STRIP");
+        if let Some(strip_components) = strip_components {
+            if !strips_natively {
+                emulate_strip_components(dir, strip_components)?;
+            }
+        }
+        Ok(Decompressed {
+            format: format_name.to_string(),
+            command: build(command).redacted(&self.redact),
+        })
+    }
+
+    /// Compress `dir` with the format's `compress` commands into an archive
+    /// next to it. Returns the path of the archive
+    pub fn compress_dir<D: AsRef<Path>>(
+        &self,
+        dir: D,
+        format_name: &str,
+    ) -> Result<PathBuf, DecompressError> {
+        let dir = dir.as_ref();
+        let dir_str = dir.to_string_lossy();
+        let (format_name, format) = self.named_format(format_name)?;
+        let Some(commands) = &format.compress.0 else {
+            return Err(DecompressError::NoCompressCommand {
+                format: format_name.to_string(),
+            });
+        };
+        let Some(archive) = compressed_file_path(dir, format) else {
+            return Err(DecompressError::NoArchiveExtension {
+                dir: dir_str.to_string(),
+                format: format_name.to_string(),
+            });
+        };
+        let archive_str = archive.to_string_lossy();
+        if archive.symlink_metadata().is_ok() {
+            return Err(DecompressError::ArchiveExists {
+                archive: archive_str.to_string(),
+            });
+        }
+        let key = self.key_file(format_name, format, commands)?;
+        self.run_first_available(
+            &dir_str,
+            format_name,
+            commands,
+            |command| {
+                command.decompress_command_format(&Substitutions {
+                    password: None,
+                    ..self.substitutions(&archive_str, &dir_str, key.as_ref())
+                })
+            },
+            None,
+        )?;
+        Ok(archive)
+    }
+
+    /// Decompress a single-stream file like `.gz` to stdout with the
+    /// format's `decompress-stdout` commands.
+    /// Returns the name of the format used
+    pub fn decompress_to_stdout<F: AsRef<Path>>(
+        &self,
+        file: F,
+    ) -> Result<String, DecompressError> {
+        let file_str = file.as_ref().to_string_lossy();
+        let (format_name, format) = self.require_format(&file_str)?;
+        self.decompress_to_stdout_as(&file_str, format_name, format)
+    }
+
+    /// Like [`FileArchiver::decompress_to_stdout`] with the format named
+    /// `format_name` instead of the one matching the file
+    pub fn decompress_to_stdout_with_format<F: AsRef<Path>>(
+        &self,
+        file: F,
+        format_name: &str,
+    ) -> Result<String, DecompressError> {
+        let file_str = file.as_ref().to_string_lossy();
+        let (format_name, format) = self.named_format(format_name)?;
+        self.decompress_to_stdout_as(&file_str, format_name, format)
+    }
+
+    fn decompress_to_stdout_as(
+        &self,
+        file_str: &str,
+        format_name: &str,
+        format: &Format,
+    ) -> Result<String, DecompressError> {
+        let Some(commands) = &format.decompress_stdout.0 else {
+            return Err(DecompressError::NoStdoutCommand {
+                file: file_str.to_string(),
+                found_format_name: format_name.to_string(),
+            });
+        };
+        let key = self.key_file(format_name, format, commands)?;
+        // the child inherits stdout
+        self.run_first_available(
+            file_str,
+            format_name,
+            commands,
+            |command| {
+                command.decompress_command_format(&self.substitutions(
+                    file_str,
+                    "",
+                    key.as_ref(),
+                ))
+            },
+            None,
+        )?;
+        Ok(format_name.to_string())
+    }
+
+    /// Decompress stdin to stdout with the `decompress-pipe` commands of the
+    /// format named `format_name`. Returns the name of the format used
+    pub fn decompress_pipe(
+        &self,
+        format_name: &str,
+    ) -> Result<String, DecompressError> {
+        let (format_name, format) = self.named_format(format_name)?;
+        let Some(commands) = &format.decompress_pipe.0 else {
+            return Err(DecompressError::NoPipeCommand {
+                format: format_name.clone(),
+            });
+        };
+        let key = self.key_file(format_name, format, commands)?;
+        // the child inherits stdin and stdout
+        self.run_first_available(
+            "stdin",
+            format_name,
+            commands,
+            |command| {
+                command.decompress_command_format(&self.substitutions(
+                    "",
+                    "",
+                    key.as_ref(),
+                ))
+            },
+            None,
+        )?;
+        Ok(format_name.clone())
+    }
+
+    /// List the entries of an archive to stdout with the format's `list`
+    /// commands. Returns the name of the format used
+    pub fn list<F: AsRef<Path>>(
+        &self,
+        file: F,
+    ) -> Result<String, DecompressError> {
+        let file_str = file.as_ref().to_string_lossy();
+        let (format_name, format) = self.require_format(&file_str)?;
+        let Some(commands) = &format.list.0 else {
+            return Err(DecompressError::NoListCommand {
+                file: file_str.to_string(),
+                found_format_name: format_name.clone(),
+            });
+        };
+        let key = self.key_file(format_name, format, commands)?;
+        self.run_first_available(
+            &file_str,
+            format_name,
+            commands,
+            |command| {
+                command.decompress_command_format(&self.substitutions(
+                    &file_str,
+                    "",
+                    key.as_ref(),
+                ))
+            },
+            None,
+        )?;
+        Ok(format_name.clone())
+    }
+
+    /// Test the integrity of an archive with the format's `verify`
+    /// commands, extracting nothing. Returns the name of the format used
+    pub fn verify<F: AsRef<Path>>(
+        &self,
+        file: F,
+    ) -> Result<String, DecompressError> {
+        let file_str = file.as_ref().to_string_lossy();
+        let (format_name, format) = self.require_format(&file_str)?;
+        let Some(commands) = &format.verify.0 else {
+            return Err(DecompressError::NoVerifyCommand {
+                file: file_str.to_string(),
+                found_format_name: format_name.clone(),
+            });
+        };
+        let key = self.key_file(format_name, format, commands)?;
+        self.run_first_available(
+            &file_str,
+            format_name,
+            commands,
+            |command| {
+                command.decompress_command_format(&self.substitutions(
+                    &file_str,
+                    "",
+                    key.as_ref(),
+                ))
+            },
+            None,
+        )?;
+        Ok(format_name.clone())
+    }
+
+    /// What every command of the archiver substitutes, with its password
+    fn substitutions<'a>(
+        &'a self,
+        file: &'a str,
+        dir: &'a str,
+        key: Option<&'a cfg::KeyFile>,
+    ) -> Substitutions<'a> {
+        Substitutions {
+            file,
+            dir,
+            password: self.password.as_deref(),
+            key,
+            root: self.chewwy_root.as_deref(),
+            output_dir: self.output_dir.as_deref(),
+            archive_dir: self.archive_dir.as_deref(),
+            ..Default::default()
+        }
+    }
+
+    /// The `key-file` of the format, read when `commands` use `{KEY}`
+    fn key_file(
+        &self,
+        format_name: &str,
+        format: &Format,
+        commands: &[cfg::Command],
+    ) -> Result<Option<cfg::KeyFile>, DecompressError> {
+        let Some(path) = format.key_file_path(self.chewwy_root.as_deref())
+        else {
+            return Ok(None);
+        };
+        let error = |io| DecompressError::KeyFile {
+            path: path.to_string_lossy().to_string(),
+            format: format_name.to_string(),
+            io,
+        };
+        let uses_key = commands
+            .iter()
+            .any(|command| command.placeholders().any(|name| name == "KEY"));
+        let key = if uses_key {
+            let key = fs::read_to_string(&path).map_err(error)?;
+            Some(key.trim_end_matches(['\r', '\n']).to_string())
+        } else {
+            fs::metadata(&path).map_err(error)?;
+            None
+        };
+        Ok(Some(cfg::KeyFile {
+            path: path.to_string_lossy().to_string(),
+            key,
+        }))
+    }
+
+    fn require_format(
+        &self,
+        file_str: &str,
+    ) -> Result<(&String, &Format), DecompressError> {
+        let Some((format_name, format)) = self
+            .find_format(file_str)
+            .map_err(DecompressError::FindFormat)?
+        else {
             return Err(DecompressError::NoFormatAvailable {
                 file: file_str.to_string(),
             });
         };
+        log::debug!("matched format \"{format_name}\" for \"{file_str}\"");
+        Ok((format_name, format))
+    }
 
-        let decompress_commands = format.decompress.c();
-        let mut child = None;
-        for decompress_command in decompress_commands {
-            let mut command = decompress_command
-                .decompress_command_format(&file_str, &dir_str);
-            match command.spawn() {
-                Ok(c) => {
-                    child = Some((c, command, decompress_command));
-                    break;
-                }
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::NotFound {
-                        continue;
-                    }
+    /// The format named `format_name` or one of its `aliases`
+    pub fn named_format(
+        &self,
+        format_name: &str,
+    ) -> Result<(&'cfg String, &'cfg Format), DecompressError> {
+        let Some(format_name) =
+            cfg::resolve_format_name(self.formats, format_name)
+        else {
+            return Err(DecompressError::UnknownFormat {
+                format: format_name.to_string(),
+            });
+        };
+        Ok((format_name, &self.formats[format_name]))
+    }
+
+    /// Spawn the first command whose executable exists, skipping the ones
+    /// not found, and wait for it. Shared by every action running
+    /// configured commands.
+    /// With `retry_cleanup`, a command exiting with an error is followed by
+    /// the next one after calling `retry_cleanup`
+    fn run_first_available<'c, B>(
+        &self,
+        file_str: &str,
+        format_name: &str,
+        commands: &'c [cfg::Command],
+        build: B,
+        retry_cleanup: Option<&dyn Fn()>,
+    ) -> Result<&'c cfg::Command, DecompressError>
+    where
+        B: Fn(&cfg::Command) -> cfg::FormattedCommand,
+    {
+        let mut attempted = vec![];
+        let mut failures = vec![];
+        for command_cfg in commands {
+            let mut formatted = build(command_cfg);
+            let command_str = formatted.redacted(&self.redact);
+            let command = &mut formatted.command;
+            if let Some(cwd) = command.get_current_dir() {
+                // otherwise spawning fails with `NotFound` as if the
+                // command doesn't exist
+                if let Err(e) = fs::create_dir_all(cwd) {
                     return Err(DecompressError::RunCommandError {
-                        command_str: format!("{command:?}"),
-                        command: decompress_command.clone(),
+                        command_str,
+                        command: Box::new(command_cfg.clone()),
                         io: e,
                         format: format_name.to_string(),
                     });
                 }
             }
-        }
-
-        let Some((mut child, command, command_cfg)) = child else {
-            return Err(DecompressError::NoCommandAvailable {
-                file: file_str.to_string(),
-                found_format_name: format_name.clone(),
-            });
-        };
-        match child.wait() {
-            Ok(o) => 'ok: {
-                if o.success() {
-                    break 'ok;
+            let options = RunOptions {
+                progress: self.progress,
+                buffer_output: self.buffer_output,
+                stdout_to_stderr: self.stdout_to_stderr,
+            };
+            log::debug!("spawning {command_str}");
+            let status = match self.runner.run(command, options) {
+                Ok(status) => Ok(status),
+                Err(RunError::Wait(e)) => Err(e),
+                Err(RunError::Spawn(e))
+                    if e.kind() == io::ErrorKind::NotFound =>
+                {
+                    log::debug!("`{}` not found", command_cfg.path);
+                    attempted.push(command_cfg.path.clone());
+                    continue;
                 }
-                if let Some(code) = o.code() {
-                    return Err(DecompressError::ChildReturnErrorCode {
-                        command_str: format!("{command:?}"),
-                        command: command_cfg.clone(),
-                        code,
-                        format: format_name.clone(),
+                Err(RunError::Spawn(e)) => {
+                    return Err(DecompressError::RunCommandError {
+                        command_str,
+                        command: Box::new(command_cfg.clone()),
+                        io: e,
+                        format: format_name.to_string(),
                     });
-                } else {
-                    return Err(DecompressError::ChildError {
-                        command_str: format!("{command:?}"),
-                        command: command_cfg.clone(),
-                        format: format_name.clone(),
+                }
+                Err(RunError::Interrupted) => {
+                    return Err(DecompressError::Interrupted {
+                        format: format_name.to_string(),
                     });
                 }
-            }
-            Err(e) => {
-                return Err(DecompressError::ChildWaitReturnError {
-                    command_str: format!("{command:?}"),
-                    command: command_cfg.clone(),
-                    format: format_name.clone(),
-                    io: e,
-                })
-            }
+            };
+            let error = match child_error(
+                status,
+                command_str,
+                command_cfg,
+                format_name,
+            ) {
+                Ok(()) => return Ok(command_cfg),
+                Err(error) => error,
+            };
+            let Some(retry_cleanup) = retry_cleanup else {
+                return Err(error);
+            };
+            log::warn!("{error}, trying the next command");
+            retry_cleanup();
+            failures.push(error);
         }
 
-        Ok(())
+        if !failures.is_empty() {
+            return Err(DecompressError::AllCommandsFailed {
+                file: file_str.to_string(),
+                format: format_name.to_string(),
+                failures,
+            });
+        }
+        Err(DecompressError::NoCommandAvailable {
+            file: file_str.to_string(),
+            found_format_name: format_name.to_string(),
+            attempted,
+        })
     }
 
-    fn find_format<P: AsRef<Path>>(
+    pub(crate) fn find_format<P: AsRef<Path>>(
         &self,
         file: P,
-    ) -> Option<(&String, &Format)> {
-        find_format(self.formats, file)
+    ) -> Result<Option<(&String, &Format)>, FindFormatError> {
+        find_format(self.formats, file, self.case_sensitive_extensions)
+    }
+
+    /// The format `file` would be decompressed with, and which of its
+    /// extensions matched
+    pub fn detect_format<P: AsRef<Path>>(
+        &self,
+        file: P,
+    ) -> Result<Option<FormatMatch<'cfg>>, FindFormatError> {
+        find_format_match(self.formats, file, self.case_sensitive_extensions)
     }
 }
 
+/// Collapse `strip_components` single nested directories of `dir`, for
+/// commands that can't strip them while decompressing
+fn emulate_strip_components(
+    dir: &Path,
+    strip_components: u32,
+) -> Result<(), DecompressError> {
+    let levels =
+        crate::utils::unnest_dir_recursive(dir, strip_components as usize)
+            .map_err(|error| DecompressError::StripComponents {
+                dir: dir.to_string_lossy().to_string(),
+                error,
+            })?;
+    if levels < strip_components as usize {
+        log::warn!(
+            "stripped {levels} of {strip_components} leading directories of \
+             `{}`, the next level has more than one entry",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// The error of a command that ran with `status`
+fn child_error(
+    status: io::Result<process::ExitStatus>,
+    command_str: String,
+    command_cfg: &cfg::Command,
+    format_name: &str,
+) -> Result<(), DecompressError> {
+    let command = Box::new(command_cfg.clone());
+    let format = format_name.to_string();
+    match status {
+        Ok(status) if command_cfg.succeeded(status) => {
+            if !status.success() {
+                log::info!(
+                    "{command_str} exited with {status}, a success code"
+                );
+            }
+            Ok(())
+        }
+        Ok(status) => match status.code() {
+            Some(code) => Err(DecompressError::ChildReturnErrorCode {
+                command_str,
+                command,
+                code,
+                format,
+            }),
+            None => Err(DecompressError::ChildError {
+                command_str,
+                command,
+                format,
+            }),
+        },
+        Err(io) => Err(DecompressError::ChildWaitReturnError {
+            command_str,
+            command,
+            format,
+            io,
+        }),
+    }
+}
+
+/// Wait for the child, with a spinner on stderr when `progress` and it
+/// takes a while. The child is killed once `interrupted`
+fn wait_with_progress(
+    child: &mut process::Child,
+    progress: bool,
+    interrupted: fn() -> bool,
+) -> io::Result<process::ExitStatus> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+    const PROGRESS_DELAY: Duration = Duration::from_secs(1);
+    const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+    let started = Instant::now();
+    let mut shown = false;
+    let mut tick = 0;
+    let mut killed = false;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if shown {
+                // clear the spinner line
+                eprint!("\r\x1b[2K");
+            }
+            return Ok(status);
+        }
+        // the child usually got the Ctrl-C too, unless it ignores it
+        if !killed && interrupted() {
+            child.kill()?;
+            killed = true;
+        }
+        let elapsed = started.elapsed();
+        if progress && elapsed >= PROGRESS_DELAY {
+            eprint!(
+                "\r{} still working... {}s",
+                SPINNER[tick % SPINNER.len()],
+                elapsed.as_secs()
+            );
+            shown = true;
+        }
+        thread::sleep(POLL_INTERVAL);
+        tick += 1;
+    }
+}
+
+/// Wait for the child with piped output, then print its output. The child
+/// is killed once `interrupted`
+fn wait_buffered(
+    mut child: process::Child,
+    stdout_to_stderr: bool,
+    interrupted: fn() -> bool,
+) -> io::Result<process::ExitStatus> {
+    use io::{Read, Write};
+    fn read_all<R: Read + Send + 'static>(
+        pipe: Option<R>,
+    ) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+        thread::spawn(move || {
+            let mut output = vec![];
+            if let Some(mut pipe) = pipe {
+                pipe.read_to_end(&mut output)?;
+            }
+            Ok(output)
+        })
+    }
+    // read both while waiting so a full pipe doesn't block the child
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+    let status = wait_with_progress(&mut child, false, interrupted)?;
+    let stdout = stdout.join().expect("stdout reader panicked")?;
+    let stderr = stderr.join().expect("stderr reader panicked")?;
+    if stdout_to_stderr {
+        io::stderr().lock().write_all(&stdout)?;
+    } else {
+        io::stdout().lock().write_all(&stdout)?;
+    }
+    io::stderr().lock().write_all(&stderr)?;
+    Ok(status)
+}
+
+/// How a file was decompressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decompressed {
+    /// Name of the format used
+    pub format: String,
+    /// The command that succeeded, shown like in logs
+    pub command: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct FormatMatch<'a> {
+    pub name: &'a String,
+    pub format: &'a Format,
+    /// Like `tar.gz`, lowercase unless extensions are case sensitive.
+    /// The last extension of the file for a wildcard format
+    pub extension: String,
+}
+
 fn find_format<P: AsRef<Path>>(
     formats: &HashMap<String, Format>,
     file: P,
-) -> Option<(&String, &Format)> {
+    case_sensitive: bool,
+) -> Result<Option<(&String, &Format)>, FindFormatError> {
+    let file = file.as_ref();
+    let found = find_format_match(formats, file, case_sensitive)?;
+    match found {
+        Some(found) => Ok(Some((found.name, found.format))),
+        #[cfg(feature = "sniff")]
+        None => find_format_by_content(formats, file),
+        #[cfg(not(feature = "sniff"))]
+        None => Ok(None),
+    }
+}
+
+fn find_format_match<P: AsRef<Path>>(
+    formats: &HashMap<String, Format>,
+    file: P,
+    case_sensitive: bool,
+) -> Result<Option<FormatMatch<'_>>, FindFormatError> {
     fn file_extension_vec<P: AsRef<Path>>(file: &P) -> Option<Vec<&str>> {
         let file = file.as_ref();
-        let file = file.components().last()?;
+        let file = file.components().next_back()?;
         let file = file.as_os_str().to_str()?;
         let extensions = file.split('.').skip(1).collect::<Vec<_>>();
         Some(extensions)
     }
 
     let file = file.as_ref();
-    let extension_vec = file_extension_vec(&file)?;
-    let extension_format_cache = ExtensionFormatCache::new(formats);
+    let Some(extension_vec) = file_extension_vec(&file) else {
+        return Ok(None);
+    };
+    let extension_format_cache =
+        ExtensionFormatCache::new(formats, case_sensitive);
     let mut found_format = None;
     for i in 0..extension_vec.len() {
         let extension_vec = &extension_vec[i..];
@@ -164,34 +1001,251 @@ fn find_format<P: AsRef<Path>>(
                 s.push('.');
                 s.push_str(ext_part);
             }
+            if !case_sensitive {
+                s.make_ascii_lowercase();
+            }
             s
         };
-        if let Some(format) =
+        if let Some(format_names) =
             extension_format_cache.extension_format.get(&extension)
         {
-            found_format = Some(format);
-            break;
+            match top_priority_format(formats, format_names) {
+                Ok(format_name) => {
+                    found_format = Some((format_name, extension));
+                    break;
+                }
+                Err(top_formats) => {
+                    return Err(FindFormatError::AmbiguousExtension {
+                        extension,
+                        formats: top_formats,
+                    })
+                }
+            }
+        }
+    }
+    if let (None, Some(last)) = (&found_format, extension_vec.last()) {
+        match extension_format_cache.wildcard_formats[..] {
+            [] => {}
+            [format_name] => {
+                let mut extension = last.to_string();
+                if !case_sensitive {
+                    extension.make_ascii_lowercase();
+                }
+                found_format = Some((format_name, extension));
+            }
+            _ => {
+                return Err(FindFormatError::AmbiguousExtension {
+                    extension: WILDCARD_EXTENSION.to_string(),
+                    formats: extension_format_cache
+                        .wildcard_formats
+                        .iter()
+                        .map(|f| f.to_string())
+                        .collect(),
+                })
+            }
         }
     }
-    found_format.map(|format_name| (*format_name, &formats[*format_name]))
+    Ok(found_format.map(|(name, extension)| FormatMatch {
+        name,
+        format: &formats[name],
+        extension,
+    }))
+}
+
+/// Find the format claiming `mime_type` in its `mime-types`, ignoring case
+/// and parameters like `; charset=binary`
+pub fn find_format_by_mime<'a>(
+    formats: &'a HashMap<String, Format>,
+    mime_type: &str,
+) -> Result<Option<(&'a String, &'a Format)>, FindFormatError> {
+    let mime_type = normalize_mime_type(mime_type);
+    let mime_format_cache = MimeFormatCache::new(formats);
+    let Some(format_names) = mime_format_cache.mime_format.get(&mime_type)
+    else {
+        return Ok(None);
+    };
+    match top_priority_format(formats, format_names) {
+        Ok(format_name) => Ok(Some((format_name, &formats[format_name]))),
+        Err(top_formats) => Err(FindFormatError::AmbiguousMimeType {
+            mime_type,
+            formats: top_formats,
+        }),
+    }
+}
+
+/// Bytes of a file read to sniff its type, enough for the tar header
+#[cfg(feature = "sniff")]
+const SNIFF_LEN: usize = 262;
+
+/// MIME type of an archive recognized by the first bytes of its content
+#[cfg(feature = "sniff")]
+pub fn sniff_mime_type(header: &[u8]) -> Option<&'static str> {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"PK\x03\x04", "application/zip"),
+        // empty and spanned zips
+        (b"PK\x05\x06", "application/zip"),
+        (b"PK\x07\x08", "application/zip"),
+        (b"Rar!\x1a\x07", "application/vnd.rar"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"BZh", "application/x-bzip2"),
+        (b"\xfd7zXZ\x00", "application/x-xz"),
+        (b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (b"\x04\x22\x4d\x18", "application/x-lz4"),
+        (b"\x1f\x9d", "application/x-compress"),
+    ];
+    let found = MAGIC
+        .iter()
+        .find(|(magic, _)| header.starts_with(magic))
+        .map(|(_, mime_type)| *mime_type);
+    found.or_else(|| {
+        (header.get(257..262) == Some(b"ustar")).then_some("application/x-tar")
+    })
+}
+
+/// Find the format claiming the MIME type sniffed from the content of
+/// `file` in its `mime-types`. An unreadable file has no format
+#[cfg(feature = "sniff")]
+pub fn find_format_by_content<'a>(
+    formats: &'a HashMap<String, Format>,
+    file: &Path,
+) -> Result<Option<(&'a String, &'a Format)>, FindFormatError> {
+    use io::Read;
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    let read = fs::File::open(file)
+        .and_then(|f| f.take(SNIFF_LEN as u64).read_to_end(&mut header));
+    if let Err(e) = read {
+        log::debug!("cannot sniff `{}`: {e}", file.display());
+        return Ok(None);
+    }
+    let Some(mime_type) = sniff_mime_type(&header) else {
+        return Ok(None);
+    };
+    log::debug!("sniffed {mime_type} from `{}`", file.display());
+    find_format_by_mime(formats, mime_type)
 }
 
+fn normalize_mime_type(mime_type: &str) -> String {
+    let essence = mime_type.split(';').next().unwrap_or_default();
+    essence.trim().to_ascii_lowercase()
+}
+
+/// The only format of the highest priority among `format_names` sorted by
+/// priority, or every format sharing that priority
+fn top_priority_format<'a>(
+    formats: &HashMap<String, Format>,
+    format_names: &[&'a String],
+) -> Result<&'a String, Vec<String>> {
+    let priority =
+        |format_name: &String| formats[format_name].priority.0.unwrap_or(0);
+    let top_priority = priority(format_names[0]);
+    let top_format_names = format_names
+        .iter()
+        .take_while(|f| priority(f) == top_priority)
+        .collect::<Vec<_>>();
+    if let [format_name] = top_format_names[..] {
+        return Ok(format_name);
+    }
+    Err(top_format_names.iter().map(|f| f.to_string()).collect())
+}
+
+/// Sort by priority from highest then by name
+fn sort_by_priority(
+    formats: &HashMap<String, Format>,
+    format_names: &mut [&String],
+) {
+    format_names.sort_by_key(|format_name| {
+        (
+            std::cmp::Reverse(formats[*format_name].priority.0.unwrap_or(0)),
+            *format_name,
+        )
+    });
+}
+
+/// Path of the archive created by compressing `dir` with `format`, next to
+/// `dir`. E.g. `mydir` -> `mydir.tar.gz`
+pub fn compressed_file_path<P: AsRef<Path>>(
+    dir: P,
+    format: &Format,
+) -> Option<PathBuf> {
+    let dir = dir.as_ref();
+    let dir_name = dir.file_name()?.to_str()?;
+    let extension = format.archive_extension()?;
+    Some(dir.with_file_name(format!("{dir_name}.{extension}")))
+}
+
+/// Extension matching any file with an extension that no other format
+/// claims
+pub const WILDCARD_EXTENSION: &str = "*";
+
 struct ExtensionFormatCache<'a> {
-    extension_format: HashMap<&'a String, &'a String>,
+    /// Every format claiming the extension, sorted by priority from highest
+    /// then by name. Extensions are lowercase unless case sensitive
+    extension_format: HashMap<String, Vec<&'a String>>,
+    /// Formats claiming `WILDCARD_EXTENSION`, sorted by name
+    wildcard_formats: Vec<&'a String>,
 }
 
 impl<'a> ExtensionFormatCache<'a> {
+    fn new(formats: &'a HashMap<String, Format>, case_sensitive: bool) -> Self {
+        let mut extension_format = HashMap::<_, Vec<_>>::new();
+        let mut wildcard_formats = vec![];
+        for (format_name, format) in formats {
+            for extension in format.extensions.c() {
+                if extension == WILDCARD_EXTENSION {
+                    wildcard_formats.push(format_name);
+                    continue;
+                }
+                let extension = if case_sensitive {
+                    extension.clone()
+                } else {
+                    extension.to_ascii_lowercase()
+                };
+                let format_names: &mut Vec<_> =
+                    extension_format.entry(extension).or_default();
+                if !format_names.contains(&format_name) {
+                    format_names.push(format_name);
+                }
+            }
+        }
+        for format_names in extension_format.values_mut() {
+            sort_by_priority(formats, format_names);
+        }
+        wildcard_formats.sort();
+        wildcard_formats.dedup();
+        ExtensionFormatCache {
+            extension_format,
+            wildcard_formats,
+        }
+    }
+}
+
+struct MimeFormatCache<'a> {
+    /// Every format claiming the lowercase MIME type, sorted by priority
+    /// from highest then by name
+    mime_format: HashMap<String, Vec<&'a String>>,
+}
+
+impl<'a> MimeFormatCache<'a> {
     fn new(formats: &'a HashMap<String, Format>) -> Self {
-        let extension_format = formats
-            .iter()
-            .flat_map(|(format_name, format)| {
-                let extensions = format.extensions.c();
-                extensions
-                    .iter()
-                    .map(move |extension| (extension, format_name))
-            })
-            .collect();
-        ExtensionFormatCache { extension_format }
+        let mut mime_format = HashMap::<_, Vec<_>>::new();
+        for (format_name, format) in formats {
+            let Some(mime_types) = &format.mime_types.0 else {
+                continue;
+            };
+            for mime_type in mime_types {
+                let format_names: &mut Vec<_> = mime_format
+                    .entry(normalize_mime_type(mime_type))
+                    .or_default();
+                if !format_names.contains(&format_name) {
+                    format_names.push(format_name);
+                }
+            }
+        }
+        for format_names in mime_format.values_mut() {
+            sort_by_priority(formats, format_names);
+        }
+        MimeFormatCache { mime_format }
     }
 }
 
@@ -223,39 +1277,828 @@ mod test {
                 s("first"),
                 F {
                     extensions: c(hashset([s("abc")])),
-                    decompress: c(vec![]),
+                    ..Default::default()
                 },
             ),
             (
                 s("second"),
                 F {
                     extensions: c(hashset([s("abc.def")])),
-                    decompress: c(vec![]),
+                    ..Default::default()
                 },
             ),
             (
                 s("third"),
                 F {
                     extensions: c(hashset([s("def")])),
-                    decompress: c(vec![]),
+                    ..Default::default()
                 },
             ),
         ]);
         assert_eq!(
             Some(&s("first")),
-            super::find_format(&formats, "a_file.abc").map(|a| a.0)
+            super::find_format(&formats, "a_file.abc", false)
+                .unwrap()
+                .map(|a| a.0)
         );
         assert_eq!(
             Some(&s("first")),
-            super::find_format(&formats, "a_file.hiya.abc").map(|a| a.0)
+            super::find_format(&formats, "a_file.hiya.abc", false)
+                .unwrap()
+                .map(|a| a.0)
         );
         assert_eq!(
             Some(&s("third")),
-            super::find_format(&formats, "a_file.def").map(|a| a.0)
+            super::find_format(&formats, "a_file.def", false)
+                .unwrap()
+                .map(|a| a.0)
         );
         assert_eq!(
             Some(&s("second")),
-            super::find_format(&formats, "a_file.abc.def").map(|a| a.0)
+            super::find_format(&formats, "a_file.abc.def", false)
+                .unwrap()
+                .map(|a| a.0)
+        );
+    }
+
+    #[test]
+    fn detect_format_extension() {
+        use super::FileArchiver;
+        use crate::cfg::Format as F;
+        let format = |extensions: &[&str]| F {
+            extensions: c(hashset(extensions.iter().map(|e| s(e)))),
+            ..Default::default()
+        };
+        let formats = HashMap::from([
+            (s("gz"), format(&["gz"])),
+            (s("targz"), format(&["tar.gz", "tgz"])),
+            (s("any"), format(&["*"])),
+        ]);
+        let archiver = FileArchiver::new(&formats);
+        let detect = |file| {
+            let found = archiver.detect_format(file).unwrap().unwrap();
+            (found.name.as_str(), found.extension)
+        };
+
+        assert_eq!(("targz", s("tar.gz")), detect("a.tar.gz"));
+        assert_eq!(("targz", s("tar.gz")), detect("a.b.TAR.Gz"));
+        assert_eq!(("targz", s("tgz")), detect("a.tgz"));
+        assert_eq!(("gz", s("gz")), detect("a.txt.gz"));
+        assert_eq!(("any", s("bin")), detect("a.tar.Bin"));
+        assert!(archiver.detect_format("a").unwrap().is_none());
+    }
+
+    #[test]
+    fn find_format_case_insensitive() {
+        use crate::cfg::Format as F;
+        let formats: HashMap<String, F> = HashMap::from_iter([
+            (
+                s("zip"),
+                F {
+                    extensions: c(hashset([s("zip")])),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("targz"),
+                F {
+                    extensions: c(hashset([s("Tar.GZ")])),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let find = |file, case_sensitive| {
+            super::find_format(&formats, file, case_sensitive)
+                .unwrap()
+                .map(|a| a.0.clone())
+        };
+        assert_eq!(Some(s("zip")), find("ARCHIVE.ZIP", false));
+        assert_eq!(Some(s("targz")), find("Photo.TAR.GZ", false));
+        assert_eq!(Some(s("targz")), find("a.b.tar.gz", false));
+        assert_eq!(None, find("ARCHIVE.ZIP", true));
+        assert_eq!(None, find("a.tar.gz", true));
+        assert_eq!(Some(s("targz")), find("a.Tar.GZ", true));
+    }
+
+    #[test]
+    fn find_format_ambiguous_extension() {
+        use super::FindFormatError;
+        use crate::cfg::Format as F;
+        let formats: HashMap<String, F> = HashMap::from_iter([
+            (
+                s("first"),
+                F {
+                    extensions: c(hashset([s("abc"), s("xyz")])),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("second"),
+                F {
+                    extensions: c(hashset([s("abc")])),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        assert_eq!(
+            Some(&s("first")),
+            super::find_format(&formats, "a_file.xyz", false)
+                .unwrap()
+                .map(|a| a.0)
+        );
+        match super::find_format(&formats, "a_file.abc", false) {
+            Err(FindFormatError::AmbiguousExtension { extension, formats }) => {
+                assert_eq!(s("abc"), extension);
+                assert_eq!(vec![s("first"), s("second")], formats);
+            }
+            other => panic!("expected ambiguity, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn find_format_wildcard() {
+        use super::FindFormatError;
+        use crate::cfg::Format as F;
+        let mut formats: HashMap<String, F> = HashMap::from_iter([
+            (
+                s("zip"),
+                F {
+                    extensions: c(hashset([s("zip")])),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("any"),
+                F {
+                    extensions: c(hashset([s("*")])),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let find = |formats: &HashMap<String, F>, file| {
+            super::find_format(formats, file, false)
+                .map(|found| found.map(|(format_name, _)| format_name.clone()))
+        };
+        assert_eq!(Some(s("zip")), find(&formats, "a.zip").unwrap());
+        assert_eq!(Some(s("any")), find(&formats, "a.tar.xz").unwrap());
+        assert_eq!(None, find(&formats, "README").unwrap());
+
+        formats.insert(
+            s("other-any"),
+            F {
+                extensions: c(hashset([s("*")])),
+                ..Default::default()
+            },
         );
+        assert_eq!(Some(s("zip")), find(&formats, "a.zip").unwrap());
+        match find(&formats, "a.xz") {
+            Err(FindFormatError::AmbiguousExtension { extension, formats }) => {
+                assert_eq!(s("*"), extension);
+                assert_eq!(vec![s("any"), s("other-any")], formats);
+            }
+            other => panic!("expected ambiguity, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn find_format_by_mime() {
+        use super::FindFormatError;
+        use crate::cfg::Format as F;
+        let formats: HashMap<String, F> = HashMap::from_iter([
+            (
+                s("zip"),
+                F {
+                    mime_types: c(hashset([s("application/zip")])),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("gz"),
+                F {
+                    mime_types: c(hashset([
+                        s("application/gzip"),
+                        s("application/x-gzip"),
+                    ])),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("other-gz"),
+                F {
+                    mime_types: c(hashset([s("application/x-gzip")])),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let find = |mime_type| {
+            super::find_format_by_mime(&formats, mime_type)
+                .map(|found| found.map(|(format_name, _)| format_name.clone()))
+        };
+        assert_eq!(Some(s("zip")), find("application/zip").unwrap());
+        assert_eq!(
+            Some(s("gz")),
+            find("Application/GZIP; charset=binary").unwrap()
+        );
+        assert_eq!(None, find("text/plain").unwrap());
+        match find("application/x-gzip") {
+            Err(FindFormatError::AmbiguousMimeType { mime_type, formats }) => {
+                assert_eq!(s("application/x-gzip"), mime_type);
+                assert_eq!(vec![s("gz"), s("other-gz")], formats);
+            }
+            other => panic!("expected ambiguity, got {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "sniff")]
+    #[test]
+    fn sniff_mime_type() {
+        let mut tar = vec![0; 512];
+        tar[..6].copy_from_slice(b"a.txt\0");
+        tar[257..265].copy_from_slice(b"ustar\x0000");
+        let headers: [(&[u8], _); 8] = [
+            (
+                b"PK\x03\x04\x14\x00\x00\x00\x08\x00",
+                Some("application/zip"),
+            ),
+            (b"PK\x05\x06\x00\x00\x00\x00", Some("application/zip")),
+            (b"Rar!\x1a\x07\x01\x00\x33\x92", Some("application/vnd.rar")),
+            (
+                b"7z\xbc\xaf\x27\x1c\x00\x04",
+                Some("application/x-7z-compressed"),
+            ),
+            (b"\x1f\x8b\x08\x00\x00\x00", Some("application/gzip")),
+            (&tar, Some("application/x-tar")),
+            (b"hello, world", None),
+            (b"", None),
+        ];
+        for (header, mime_type) in headers {
+            assert_eq!(mime_type, super::sniff_mime_type(header), "{header:?}");
+        }
+    }
+
+    #[cfg(feature = "sniff")]
+    #[test]
+    fn find_format_by_content() {
+        use crate::cfg::Format as F;
+        use std::{fs, path::Path};
+        let formats: HashMap<String, F> = HashMap::from_iter([
+            (
+                s("zip"),
+                F {
+                    extensions: c(hashset([s("zip")])),
+                    mime_types: c(hashset([s("application/zip")])),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("gz"),
+                F {
+                    extensions: c(hashset([s("gz")])),
+                    mime_types: c(hashset([s("application/x-gzip")])),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let tmp = tempfile::tempdir().unwrap();
+        let download = tmp.path().join("download");
+        let find = |file: &Path| {
+            let found = super::find_format(&formats, file, false).unwrap();
+            found.map(|(name, _)| name)
+        };
+
+        fs::write(&download, b"PK\x03\x04\x14\x00").unwrap();
+        assert_eq!(Some(&s("zip")), find(&download));
+        // the extension comes first
+        let named = tmp.path().join("download.gz");
+        fs::write(&named, b"PK\x03\x04\x14\x00").unwrap();
+        assert_eq!(Some(&s("gz")), find(&named));
+        // no format claims the sniffed application/gzip
+        fs::write(&download, b"\x1f\x8b\x08\x00").unwrap();
+        assert_eq!(None, find(&download));
+        fs::write(&download, b"just text").unwrap();
+        assert_eq!(None, find(&download));
+        assert_eq!(None, find(&tmp.path().join("missing")));
+    }
+
+    #[test]
+    fn find_format_priority() {
+        use crate::cfg::Format as F;
+        let formats: HashMap<String, F> = HashMap::from_iter([
+            (
+                s("generic"),
+                F {
+                    extensions: c(hashset([s("gz")])),
+                    priority: c(5),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("special"),
+                F {
+                    extensions: c(hashset([s("gz")])),
+                    priority: c(10),
+                    ..Default::default()
+                },
+            ),
+            (
+                s("targz"),
+                F {
+                    extensions: c(hashset([s("tar.gz")])),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        // same extension length, higher priority wins
+        assert_eq!(
+            Some(&s("special")),
+            super::find_format(&formats, "a_file.gz", false)
+                .unwrap()
+                .map(|a| a.0)
+        );
+        // longer extension wins regardless of priority
+        assert_eq!(
+            Some(&s("targz")),
+            super::find_format(&formats, "a_file.tar.gz", false)
+                .unwrap()
+                .map(|a| a.0)
+        );
+    }
+
+    #[test]
+    fn compressed_file_path() {
+        use crate::cfg::Format as F;
+        use std::path::PathBuf;
+        let targz = F {
+            extensions: c(hashset([s("tgz"), s("tar.gz")])),
+            canonical_extension: c(s("tar.gz")),
+            ..Default::default()
+        };
+        let zip = F {
+            extensions: c(hashset([s("zip")])),
+            ..Default::default()
+        };
+        let sorted = F {
+            extensions: c(hashset([s("xz"), s("txz"), s("tar.xz")])),
+            ..Default::default()
+        };
+        let none = F::default();
+        assert_eq!(
+            Some(PathBuf::from("./mydir.tar.gz")),
+            super::compressed_file_path("./mydir", &targz)
+        );
+        assert_eq!(
+            Some(PathBuf::from("/a/b/mydir.zip")),
+            super::compressed_file_path("/a/b/mydir/", &zip)
+        );
+        assert_eq!(
+            Some(PathBuf::from("mydir.tar.xz")),
+            super::compressed_file_path("mydir", &sorted)
+        );
+        assert_eq!(None, super::compressed_file_path("mydir", &none));
+        assert_eq!(None, super::compressed_file_path("..", &zip));
+    }
+
+    #[test]
+    fn list_without_command() {
+        use super::{DecompressError, FileArchiver};
+        use crate::cfg::Format as F;
+        let formats = HashMap::from([(
+            s("zip"),
+            F {
+                extensions: c(hashset([s("zip")])),
+                ..Default::default()
+            },
+        )]);
+        assert!(matches!(
+            FileArchiver::new(&formats).list("a.zip"),
+            Err(DecompressError::NoListCommand { .. })
+        ));
+    }
+
+    #[test]
+    fn decompress_to_stdout_without_command() {
+        use super::{DecompressError, FileArchiver};
+        use crate::cfg::Format as F;
+        let formats = HashMap::from([(
+            s("zip"),
+            F {
+                extensions: c(hashset([s("zip")])),
+                ..Default::default()
+            },
+        )]);
+        assert!(matches!(
+            FileArchiver::new(&formats).decompress_to_stdout("a.zip"),
+            Err(DecompressError::NoStdoutCommand { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_first_available() {
+        use super::{DecompressError, FileArchiver};
+        use crate::cfg::{Command, Substitutions};
+        let command = |path: &str, args: &[&str]| Command {
+            path: s(path),
+            args: args.iter().map(|arg| s(arg)).collect(),
+            ..Default::default()
+        };
+        let formats = HashMap::new();
+        let file_archiver = FileArchiver::new(&formats);
+        let run = |commands: &[Command]| {
+            file_archiver
+                .run_first_available(
+                    "a.zip",
+                    "zip",
+                    commands,
+                    |c| {
+                        c.decompress_command_format(&Substitutions {
+                            file: "a.zip",
+                            dir: "a",
+                            ..Default::default()
+                        })
+                    },
+                    None,
+                )
+                .map(|_| ())
+        };
+
+        let missing = command("chewwy-missing-command", &[]);
+        assert!(run(&[missing.clone(), command("true", &[])]).is_ok());
+        match run(&[missing.clone(), missing.clone()]) {
+            Err(DecompressError::NoCommandAvailable { attempted, .. }) => {
+                assert_eq!(vec![missing.path.clone(); 2], attempted)
+            }
+            other => panic!("expected no command available, got {other:?}"),
+        }
+        assert!(matches!(
+            run(&[missing, command("sh", &["-c", "exit 3"])]),
+            Err(DecompressError::ChildReturnErrorCode { code: 3, .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wait_kills_interrupted_child() {
+        use std::{
+            process,
+            time::{Duration, Instant},
+        };
+        let spawn = || {
+            process::Command::new("sleep")
+                .arg("10")
+                .stdout(process::Stdio::piped())
+                .stderr(process::Stdio::piped())
+                .spawn()
+                .unwrap()
+        };
+        let started = Instant::now();
+        let status =
+            super::wait_with_progress(&mut spawn(), false, || true).unwrap();
+        assert!(!status.success());
+        let status = super::wait_buffered(spawn(), true, || true).unwrap();
+        assert!(!status.success());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn decompress_try_next_on_failure() {
+        use super::{DecompressError, FileArchiver};
+        use crate::cfg::{Command, Format as F};
+        let tmp = tempfile::tempdir().unwrap();
+        let out = tmp.path().join("out");
+        let partial =
+            Command::new("sh", &["-c", "mkdir \"$0\" && exit 2", "{DIR}"]);
+        let format = |try_next_on_failure, second: Command| F {
+            extensions: c(hashset([s("zip")])),
+            decompress: c(vec![partial.clone(), second]),
+            try_next_on_failure: c(try_next_on_failure),
+            ..Default::default()
+        };
+        let decompress = |format| {
+            let formats = HashMap::from([(s("zip"), format)]);
+            FileArchiver::new(&formats).decompress_to_dir("a.zip", &out)
+        };
+
+        let second = Command::new("mkdir", &["{DIR}"]);
+        assert!(matches!(
+            decompress(format(false, second.clone())),
+            Err(DecompressError::ChildReturnErrorCode { code: 2, .. })
+        ));
+        std::fs::remove_dir(&out).unwrap();
+
+        // the partial output is removed so the second `mkdir` succeeds
+        assert_eq!(s("zip"), decompress(format(true, second)).unwrap().format);
+        assert!(out.is_dir());
+        std::fs::remove_dir(&out).unwrap();
+
+        let failing = Command::new("sh", &["-c", "exit 3"]);
+        match decompress(format(true, failing)) {
+            Err(DecompressError::AllCommandsFailed { failures, .. }) => {
+                assert_eq!(2, failures.len())
+            }
+            other => panic!("expected every command to fail, got {other:?}"),
+        }
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn decompress_error_classification() {
+        use super::{DecompressError as E, FindFormatError};
+        use crate::cfg::Command;
+        let code = |code| E::ChildReturnErrorCode {
+            command_str: s("unzip"),
+            command: Box::new(Command::new("unzip", &[])),
+            code,
+            format: s("zip"),
+        };
+        let missing = E::NoCommandAvailable {
+            file: s("a.zip"),
+            found_format_name: s("zip"),
+            attempted: vec![s("unzip")],
+        };
+        let ambiguous = E::FindFormat(FindFormatError::AmbiguousExtension {
+            extension: s("zip"),
+            formats: vec![s("a"), s("b")],
+        });
+        let no_format = E::NoFormatAvailable { file: s("a.txt") };
+
+        assert!(missing.is_missing_command());
+        assert!(!missing.is_recoverable());
+        assert!(ambiguous.is_config_problem());
+        assert!(!ambiguous.is_recoverable());
+        assert!(code(9).is_archive_corrupt());
+        assert!(!code(1).is_archive_corrupt());
+        assert!(code(1).is_recoverable());
+        assert!(!code(1).is_config_problem());
+        assert!(no_format.is_recoverable());
+        assert!(!no_format.is_archive_corrupt());
+
+        let all_failed = |failures| E::AllCommandsFailed {
+            file: s("a.zip"),
+            format: s("zip"),
+            failures,
+        };
+        assert!(all_failed(vec![code(2), code(3)]).is_archive_corrupt());
+        assert!(!all_failed(vec![code(2), code(1)]).is_archive_corrupt());
+    }
+
+    #[test]
+    fn decompress_success_codes() {
+        use super::{DecompressError, FileArchiver};
+        use crate::{
+            cfg::{Command, Configure, Format as F},
+            testing::FakeRunner,
+        };
+        use std::sync::Arc;
+        let decompress = |code, success_codes: Option<Vec<i32>>| {
+            let command = Command {
+                success_codes: Configure(success_codes),
+                ..Command::new("unzip", &["{FILE}"])
+            };
+            let format = F {
+                extensions: c(hashset([s("zip")])),
+                decompress: c(vec![command]),
+                ..Default::default()
+            };
+            let formats = HashMap::from([(s("zip"), format)]);
+            FileArchiver::new(&formats)
+                .runner(Arc::new(FakeRunner::new().exits("unzip", code)))
+                .decompress_to_dir("a.zip", "out")
+                .map(|_| ())
+        };
+
+        assert!(decompress(0, None).is_ok());
+        assert!(matches!(
+            decompress(1, None),
+            Err(DecompressError::ChildReturnErrorCode { code: 1, .. })
+        ));
+        assert!(decompress(1, Some(vec![0, 1])).is_ok());
+        assert!(matches!(
+            decompress(2, Some(vec![0, 1])),
+            Err(DecompressError::ChildReturnErrorCode { code: 2, .. })
+        ));
+        assert!(decompress(0, Some(vec![1])).is_err());
+    }
+
+    #[test]
+    fn decompress_with_key_file() {
+        use super::{DecompressError, FileArchiver};
+        use crate::{
+            cfg::{Command, Configure, Format as F},
+            testing::FakeRunner,
+        };
+        use std::{fs, io, path::PathBuf, sync::Arc};
+        let root = tempfile::tempdir().unwrap();
+        let format = |args: &[&str]| F {
+            extensions: c(hashset([s("gpg")])),
+            decompress: c(vec![Command::new("gpg", args)]),
+            key_file: Configure(Some(Some(PathBuf::from("keys/a.key")))),
+            ..Default::default()
+        };
+        let decompress = |format: F| {
+            let formats = HashMap::from([(s("gpg"), format)]);
+            let runner = Arc::new(FakeRunner::new().exits("gpg", 0));
+            let result = FileArchiver::new(&formats)
+                .runner(runner.clone())
+                .chewwy_root(Some(root.path()))
+                .decompress_to_dir("a.gpg", "out");
+            (result.map(|_| ()), runner.runs())
+        };
+
+        let (result, runs) = decompress(format(&["{KEY_FILE}"]));
+        assert!(matches!(
+            result,
+            Err(DecompressError::KeyFile { io, .. })
+                if io.kind() == io::ErrorKind::NotFound
+        ));
+        assert!(runs.is_empty());
+
+        fs::create_dir(root.path().join("keys")).unwrap();
+        fs::write(root.path().join("keys/a.key"), "s3cret\n").unwrap();
+        let key_file = root.path().join("keys/a.key");
+        let (result, runs) = decompress(format(&["{KEY_FILE}", "-k{KEY}"]));
+        result.unwrap();
+        assert_eq!(
+            vec![vec![
+                s("gpg"),
+                key_file.to_string_lossy().to_string(),
+                s("-ks3cret")
+            ]],
+            runs
+        );
+    }
+
+    #[test]
+    fn decompress_with_fake_runner() {
+        use super::{DecompressError, FileArchiver};
+        use crate::{
+            cfg::{Command, Format as F},
+            testing::FakeRunner,
+        };
+        use std::sync::Arc;
+        let format = |extension: &str, programs: &[&str]| F {
+            extensions: c(hashset([s(extension)])),
+            decompress: c(programs
+                .iter()
+                .map(|program| Command::new(*program, &["{FILE}"]))
+                .collect()),
+            ..Default::default()
+        };
+        let formats = HashMap::from([
+            (s("zip"), format("zip", &["7z", "unzip"])),
+            (s("tar"), format("tar", &["tar"])),
+        ]);
+        let decompress = |runner: FakeRunner, file: &str| {
+            let runner = Arc::new(runner);
+            let result = FileArchiver::new(&formats)
+                .runner(runner.clone())
+                .decompress_to_dir(file, "out");
+            (result, runner.programs())
+        };
+        let installed = || FakeRunner::new().exits("unzip", 0).exits("tar", 0);
+
+        // the format is found by extension, a missing program is skipped
+        let (format, programs) = decompress(installed(), "a.zip");
+        assert_eq!(s("zip"), format.unwrap().format);
+        assert_eq!(vec![s("7z"), s("unzip")], programs);
+        let (format, programs) = decompress(installed(), "a.tar");
+        assert_eq!(s("tar"), format.unwrap().format);
+        assert_eq!(vec![s("tar")], programs);
+
+        // the first available program's exit code decides
+        let runner = installed().exits("7z", 2);
+        let (result, programs) = decompress(runner, "a.zip");
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error,
+            DecompressError::ChildReturnErrorCode { code: 2, .. }
+        ));
+        assert!(error.is_archive_corrupt());
+        assert_eq!(vec![s("7z")], programs);
+
+        let (result, programs) = decompress(FakeRunner::new(), "a.zip");
+        assert!(result.unwrap_err().is_missing_command());
+        assert_eq!(vec![s("7z"), s("unzip")], programs);
+    }
+
+    #[test]
+    fn decompress_pipe() {
+        use super::{DecompressError, FileArchiver};
+        use crate::{
+            cfg::{Command, Format as F},
+            testing::FakeRunner,
+        };
+        use std::sync::Arc;
+        let formats = HashMap::from([
+            (
+                s("gz"),
+                F {
+                    aliases: c(hashset([s("gzip")])),
+                    decompress_pipe: c(vec![Command::new("gzip", &["-dc"])]),
+                    ..Default::default()
+                },
+            ),
+            (s("zip"), F::default()),
+        ]);
+        let runner = Arc::new(FakeRunner::new().exits("gzip", 0));
+        let file_archiver = FileArchiver::new(&formats).runner(runner.clone());
+
+        assert_eq!(s("gz"), file_archiver.decompress_pipe("gzip").unwrap());
+        assert_eq!(vec![vec![s("gzip"), s("-dc")]], runner.runs());
+        assert!(matches!(
+            file_archiver.decompress_pipe("zip"),
+            Err(DecompressError::NoPipeCommand { .. })
+        ));
+        assert!(matches!(
+            file_archiver.decompress_pipe("rar"),
+            Err(DecompressError::UnknownFormat { .. })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn decompress_strip_components() {
+        use super::FileArchiver;
+        use crate::cfg::{Command, Format as F};
+        let tmp = tempfile::tempdir().unwrap();
+        let format = |command: Command| F {
+            extensions: c(hashset([s("tar")])),
+            decompress: c(vec![command]),
+            strip_components: c(2),
+            ..Default::default()
+        };
+        let decompress = |format, out: &std::path::Path| {
+            let formats = HashMap::from([(s("tar"), format)]);
+            FileArchiver::new(&formats)
+                .decompress_to_dir("a.tar", out)
+                .unwrap();
+        };
+
+        // emulated by collapsing the nested directories
+        let out = tmp.path().join("emulated");
+        let nested = Command::new(
+            "sh",
+            &["-c", "mkdir -p \"$0/a/b\" && touch \"$0/a/b/f\"", "{DIR}"],
+        );
+        decompress(format(nested), &out);
+        assert!(out.join("f").is_file());
+
+        // the command strips, the output is kept as is
+        let out = tmp.path().join("native");
+        let native = Command::new(
+            "sh",
+            &[
+                "-c",
+                "mkdir -p \"$0/a\" && echo \"$1\" > \"$0/a/strip\"",
+                "{DIR}",
+                "{STRIP}",
+            ],
+        );
+        decompress(format(native), &out);
+        assert_eq!(
+            "2\n",
+            std::fs::read_to_string(out.join("a/strip")).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn password_hidden_from_error() {
+        use super::FileArchiver;
+        use crate::cfg::Command;
+        let formats = HashMap::new();
+        let file_archiver = FileArchiver::new(&formats)
+            .password(Some(s("hunter2")))
+            .redact(vec![s("secret")]);
+        let command = Command {
+            path: s("sh"),
+            args: vec![
+                s("-c"),
+                s("exit 1"),
+                s("-p{PASSWORD}"),
+                s("--token=secret-arg"),
+            ],
+            env: HashMap::from_iter([(s("TOKEN"), s("secret-env"))]),
+            ..Default::default()
+        };
+        let error = file_archiver
+            .run_first_available(
+                "a.zip",
+                "zip",
+                &[command],
+                |c| {
+                    c.decompress_command_format(
+                        &file_archiver.substitutions("a.zip", "a", None),
+                    )
+                },
+                None,
+            )
+            .unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("\"exit 1\" ***"), "{message}");
+        assert!(!message.contains("hunter2"), "{message}");
+        assert!(message.contains("TOKEN=***"), "{message}");
+        assert!(!message.contains("secret"), "{message}");
     }
 }
@@ -0,0 +1,58 @@
+//! Ctrl-C while a command runs stops the command and fails the action with
+//! `DecompressError::Interrupted`, so its partial output is cleaned up on
+//! the way out. Otherwise, like at a prompt, Ctrl-C ends chewwy as usual
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Commands being waited for, across threads
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Handle Ctrl-C for the rest of the process. Does nothing without the
+/// `interrupt-cleanup` feature or off unix
+pub fn install() {
+    #[cfg(all(unix, feature = "interrupt-cleanup"))]
+    // SAFETY: the handler only touches atomics and calls async-signal-safe
+    // functions
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+#[cfg(all(unix, feature = "interrupt-cleanup"))]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    if RUNNING.load(Ordering::SeqCst) == 0 {
+        // SAFETY: both are async-signal-safe
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::raise(libc::SIGINT);
+        }
+        return;
+    }
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Whether Ctrl-C was pressed while a command ran. Stays set, so no more
+/// commands are started
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Counts as a running command until dropped
+pub(crate) struct Running(());
+
+impl Running {
+    pub(crate) fn start() -> Running {
+        RUNNING.fetch_add(1, Ordering::SeqCst);
+        Running(())
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -14,33 +14,246 @@ pub mod prelude {
     pub use thiserror::Error;
 }
 pub mod cfg;
+pub mod clean;
+pub mod compress;
+pub mod doctor;
 pub mod file_archiver;
+pub mod interrupt;
+pub mod manage;
+pub mod schema;
+#[cfg(test)]
+mod testing;
 pub mod utils;
+#[cfg(feature = "watch")]
+pub mod watch;
+
+/// A command needing the chewwy root ran outside of one
+#[derive(Debug, Error)]
+#[error("chewwy root not found")]
+pub struct NoChewwyRoot;
+
+/// Make `dir` a chewwy root by creating its `.chewwy` directory. Fails
+/// with `AlreadyExists` if there is one
+pub fn init_chewwy_root<P: AsRef<Path>>(dir: P) -> io::Result<PathBuf> {
+    fs::create_dir(dir.as_ref().join(DOT_DIR))?;
+    Ok(dir.as_ref().to_path_buf())
+}
 
 pub fn search_chewwy_root<P: AsRef<Path>>(
     start_at_dir: P,
 ) -> io::Result<Option<PathBuf>> {
-    for entry in fs::read_dir(&start_at_dir)? {
-        let Ok(entry) = entry else {
-            continue;
-        };
-        let Ok(file_type) = entry.file_type() else {
-            continue;
-        };
-        if !file_type.is_dir() {
-            continue;
+    search_chewwy_root_bounded(start_at_dir, &SearchBound::default())
+}
+
+/// Where `search_chewwy_root_bounded` should stop ascending.
+/// The directory a bound stops at is still searched.
+#[derive(Debug, Default, Clone)]
+pub struct SearchBound {
+    /// Maximum number of parent directories to ascend
+    pub max_ascend: Option<usize>,
+    /// Stop at this directory
+    pub stop_at: Option<PathBuf>,
+    /// Stop at a directory containing `.git`
+    pub stop_at_git: bool,
+}
+
+impl SearchBound {
+    /// Stop at `$HOME`, or unbounded if it is not set
+    pub fn home() -> SearchBound {
+        SearchBound {
+            stop_at: home_dir(),
+            ..Default::default()
         }
-        let path = entry.path();
-        let Some(name) = path.file_name() else {
-            continue;
-        };
-        if name == DOT_DIR {
-            return Ok(Some(path.parent().unwrap().to_path_buf()));
+    }
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+pub fn search_chewwy_root_bounded<P: AsRef<Path>>(
+    start_at_dir: P,
+    bound: &SearchBound,
+) -> io::Result<Option<PathBuf>> {
+    let mut dir = start_at_dir.as_ref();
+    let mut ascended = 0;
+    loop {
+        if contains_entry(dir, DOT_DIR, true) {
+            return Ok(Some(dir.to_path_buf()));
         }
+        if let Some(root) = read_root_redirect(dir)? {
+            return Ok(Some(root));
+        }
+        if bound.max_ascend.is_some_and(|max| ascended >= max)
+            || bound.stop_at.as_deref() == Some(dir)
+            || (bound.stop_at_git && contains_entry(dir, ".git", false))
+        {
+            return Ok(None);
+        }
+        let Some(parent) = dir.parent() else {
+            return Ok(None);
+        };
+        dir = parent;
+        ascended += 1;
     }
-    // search_chewwy_root()
-    let Some(parent) = start_at_dir.as_ref().parent() else {
+}
+
+/// A `.chewwy` file holds the path to the chewwy root to use instead,
+/// relative to `dir`. An empty file is not a redirect
+fn read_root_redirect(dir: &Path) -> io::Result<Option<PathBuf>> {
+    let redirect_file = dir.join(DOT_DIR);
+    match fs::metadata(&redirect_file) {
+        Ok(metadata) if metadata.is_file() => {}
+        _ => return Ok(None),
+    }
+    let content = fs::read_to_string(&redirect_file)?;
+    let target = content.trim();
+    if target.is_empty() {
         return Ok(None);
-    };
-    search_chewwy_root(parent)
+    }
+    let root = utils::normalize_lexically(dir.join(target));
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "`{}` redirects to `{}` which is not a directory",
+                redirect_file.display(),
+                root.display()
+            ),
+        ));
+    }
+    log::debug!(
+        "`{}` redirects to `{}`",
+        redirect_file.display(),
+        root.display()
+    );
+    Ok(Some(root))
+}
+
+/// Checks `dir/entry_name` directly instead of scanning `dir`, so an
+/// unreadable `dir` doesn't hide an entry that is there
+fn contains_entry(dir: &Path, entry_name: &str, must_be_dir: bool) -> bool {
+    match fs::metadata(dir.join(entry_name)) {
+        Ok(metadata) => !must_be_dir || metadata.is_dir(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tree() -> (tempfile::TempDir, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let deep = tmp.path().join("a/b/c");
+        fs::create_dir_all(&deep).unwrap();
+        fs::create_dir(tmp.path().join(DOT_DIR)).unwrap();
+        (tmp, deep)
+    }
+
+    #[test]
+    fn search_chewwy_root_unbounded() {
+        let (tmp, deep) = tree();
+        assert_eq!(
+            Some(tmp.path().to_path_buf()),
+            search_chewwy_root(&deep).unwrap()
+        );
+    }
+
+    #[test]
+    fn search_chewwy_root_from_dot_dir() {
+        let (tmp, _) = tree();
+        let dot_dir = tmp.path().join(DOT_DIR);
+        assert_eq!(
+            Some(tmp.path().to_path_buf()),
+            search_chewwy_root(&dot_dir).unwrap()
+        );
+        let file = tmp.path().join("a/b/c/.chewwy");
+        fs::write(&file, "").unwrap();
+        assert_eq!(
+            Some(tmp.path().to_path_buf()),
+            search_chewwy_root(file.parent().unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn search_chewwy_root_redirect() {
+        let (tmp, deep) = tree();
+        let real = tmp.path().join("real");
+        fs::create_dir_all(real.join(DOT_DIR)).unwrap();
+        fs::write(tmp.path().join("a/.chewwy"), "../real\n").unwrap();
+        assert_eq!(Some(real.clone()), search_chewwy_root(&deep).unwrap());
+
+        // a `.chewwy` directory closer than the redirect wins
+        fs::create_dir(deep.join(DOT_DIR)).unwrap();
+        assert_eq!(Some(deep.clone()), search_chewwy_root(&deep).unwrap());
+        fs::remove_dir(deep.join(DOT_DIR)).unwrap();
+
+        fs::write(tmp.path().join("a/.chewwy"), "../missing").unwrap();
+        let error = search_chewwy_root(&deep).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, error.kind());
+        assert!(error.to_string().contains("missing"), "{error}");
+    }
+
+    #[test]
+    fn init_chewwy_root_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(None, search_chewwy_root(tmp.path()).unwrap());
+        init_chewwy_root(tmp.path()).unwrap();
+        assert_eq!(
+            Some(tmp.path().to_path_buf()),
+            search_chewwy_root(tmp.path()).unwrap()
+        );
+        let error = init_chewwy_root(tmp.path()).unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, error.kind());
+    }
+
+    #[test]
+    fn search_chewwy_root_max_ascend() {
+        let (tmp, deep) = tree();
+        let bound = |max| SearchBound {
+            max_ascend: Some(max),
+            ..Default::default()
+        };
+        assert_eq!(None, search_chewwy_root_bounded(&deep, &bound(2)).unwrap());
+        assert_eq!(
+            Some(tmp.path().to_path_buf()),
+            search_chewwy_root_bounded(&deep, &bound(3)).unwrap()
+        );
+    }
+
+    #[test]
+    fn search_chewwy_root_stop_at() {
+        let (tmp, deep) = tree();
+        let bound = |dir: &str| SearchBound {
+            stop_at: Some(tmp.path().join(dir)),
+            ..Default::default()
+        };
+        assert_eq!(
+            None,
+            search_chewwy_root_bounded(&deep, &bound("a")).unwrap()
+        );
+        assert_eq!(
+            Some(tmp.path().to_path_buf()),
+            search_chewwy_root_bounded(&deep, &bound("")).unwrap()
+        );
+    }
+
+    #[test]
+    fn search_chewwy_root_stop_at_git() {
+        let (tmp, deep) = tree();
+        let bound = SearchBound {
+            stop_at_git: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(tmp.path().to_path_buf()),
+            search_chewwy_root_bounded(&deep, &bound).unwrap()
+        );
+        fs::create_dir(tmp.path().join("a/.git")).unwrap();
+        assert_eq!(None, search_chewwy_root_bounded(&deep, &bound).unwrap());
+    }
 }
@@ -0,0 +1,2479 @@
+use crate::prelude::*;
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread, time,
+};
+
+use serde::Serialize;
+
+use crate::{
+    cfg::{self, Cfg, StructMerge},
+    file_archiver::{self, FileArchiver},
+    utils,
+};
+
+#[derive(Debug, Error)]
+#[error("manage error")]
+pub struct ManageError;
+
+/// The user chose not to continue, like closing the prompt
+#[derive(Debug, Error)]
+#[error("cancelled")]
+pub struct Cancelled;
+
+#[derive(Debug, Default, Clone)]
+pub struct ManageOptions {
+    /// File to manage, searched for in the search directory if `None`
+    pub file: Option<PathBuf>,
+    /// Don't touch the filesystem, only report what would be done
+    pub dry_run: bool,
+    /// Takes precedence over `commands.manage.directories`
+    pub directories: cfg::Directories,
+    /// Move the compressed file to this directory instead of doing the
+    /// configured `compressed-file-action`
+    pub move_to: Option<PathBuf>,
+    /// How to choose among the files found in the search directory
+    pub selection: Selection,
+    /// Takes precedence over `commands.manage.output-file-action`
+    pub output_file_action: Option<cfg::OutputFileAction>,
+    /// Print the output of decompress commands at once when they are done
+    pub buffer_output: bool,
+    /// Decompress directly into this directory instead of a directory
+    /// named after the file in the `output` directory
+    pub to: Option<PathBuf>,
+    /// Takes precedence over `commands.manage.post-decompress`
+    pub after_cmd: Option<Vec<String>>,
+    /// Password of an encrypted archive, asked for when unset and the
+    /// format `needs-password`
+    pub password: Option<String>,
+    /// Don't do the configured `space-check`
+    pub skip_space_check: bool,
+    /// Print messages and the stdout of commands on stderr
+    pub messages_to_stderr: bool,
+    /// List symlinks to directories when searching even if
+    /// `commands.manage.follow-symlinks` is off
+    pub follow_symlinks: bool,
+    /// Leave out searched files modified longer ago than this
+    pub since: Option<time::Duration>,
+    /// Takes precedence over `commands.manage.search-sort`
+    pub sort: Option<cfg::SortKey>,
+    /// Takes precedence over `commands.manage.search-depth`
+    pub search_depth: Option<usize>,
+    /// Takes precedence over `commands.manage.search-max-entries`
+    pub max_entries: Option<usize>,
+    /// Name of the format to decompress with instead of the one matching
+    /// the file
+    pub format: Option<String>,
+    /// Runs the decompress commands instead of spawning them
+    pub runner: Option<Arc<dyn file_archiver::CommandRunner>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    /// Ask on stdin
+    #[default]
+    Prompt,
+    /// Choose the only file found, error if there are more
+    Single,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ManageSummary {
+    /// Name of the format the file was decompressed with
+    pub format: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    /// Where the compressed file was moved to
+    pub archive: Option<PathBuf>,
+    /// What the decompressed directory contained before it was normalized
+    pub layout: Option<OutputLayout>,
+    pub unnested: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputLayout {
+    Empty,
+    SingleDir,
+    SingleFile,
+    Flat,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct VerifySummary {
+    pub file: PathBuf,
+    /// Name of the format the file was verified with
+    pub format: Option<String>,
+    pub status: VerifyStatus,
+    /// Why the file is not `Ok`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyStatus {
+    Ok,
+    /// The verify command found the archive damaged
+    Corrupt,
+    /// The file couldn't be verified, like without a `verify` command
+    Failed,
+}
+
+impl fmt::Display for VerifySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            VerifyStatus::Ok => "OK",
+            VerifyStatus::Corrupt => "CORRUPT",
+            VerifyStatus::Failed => "FAILED",
+        };
+        write!(f, "[{status}] {}", self.file.display())?;
+        if let Some(error) = &self.error {
+            write!(f, ": {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Test the integrity of every file with the `verify` commands of its
+/// format, extracting nothing. Uses the password and runner of `options`
+pub fn run_verify(
+    cfg: &Cfg,
+    chewwy_root: Option<&Path>,
+    options: &ManageOptions,
+    files: Vec<PathBuf>,
+) -> StackResult<Vec<VerifySummary>, ManageError> {
+    let options_cfg = cfg.options.c();
+    let mut directories = options.directories.clone();
+    directories.struct_merge(cfg.commands.c().manage.c().directories.c());
+    let mut file_archiver = FileArchiver::new(cfg.formats.c())
+        .chewwy_root(chewwy_root)
+        .buffer_output(options.buffer_output)
+        .stdout_to_stderr(true)
+        .case_sensitive_extensions(*options_cfg.case_sensitive_extensions.c())
+        .redact(options_cfg.redact.c().clone())
+        .password(options.password.clone());
+    if let Some(runner) = &options.runner {
+        file_archiver = file_archiver.runner(runner.clone());
+    }
+    if let Some(chewwy_root) = chewwy_root {
+        file_archiver =
+            file_archiver.directories(&directories.to_absolute(chewwy_root));
+    }
+    let mut verified = vec![];
+    for file in files {
+        let result = file_archiver.verify(&file);
+        let (format, status, error) = match result {
+            Ok(format) => (Some(format), VerifyStatus::Ok, None),
+            Err(e @ file_archiver::DecompressError::Interrupted { .. }) => {
+                return Err(e).change_context(ManageError);
+            }
+            Err(e) => {
+                let format = file_archiver
+                    .find_format(&file)
+                    .ok()
+                    .flatten()
+                    .map(|(format_name, _)| format_name.clone());
+                let status = if e.is_archive_corrupt() {
+                    VerifyStatus::Corrupt
+                } else {
+                    VerifyStatus::Failed
+                };
+                (format, status, Some(e.to_string()))
+            }
+        };
+        verified.push(VerifySummary {
+            file,
+            format,
+            status,
+            error,
+        });
+    }
+    Ok(verified)
+}
+
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub managed: Vec<ManageSummary>,
+    pub failed: Vec<(PathBuf, error_stack::Report<ManageError>)>,
+}
+
+/// Manage every file with the same options, up to `jobs` at a time.
+/// Stops starting new files at the first failure unless `keep_going`.
+/// Results are in the order of `files`
+pub fn run_batch(
+    cfg: &Cfg,
+    chewwy_root: Option<&Path>,
+    options: ManageOptions,
+    files: Vec<PathBuf>,
+    keep_going: bool,
+    jobs: usize,
+) -> BatchSummary {
+    let jobs = jobs.clamp(1, files.len().max(1));
+    let options = ManageOptions {
+        buffer_output: options.buffer_output || jobs > 1,
+        ..options
+    };
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let mut results = thread::scope(|scope| {
+        let workers = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = vec![];
+                    while !stop.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(file) = files.get(i) else {
+                            break;
+                        };
+                        let options = ManageOptions {
+                            file: Some(file.clone()),
+                            ..options.clone()
+                        };
+                        let result = run(cfg, chewwy_root, options);
+                        if result.is_err() && !keep_going {
+                            stop.store(true, Ordering::Relaxed);
+                        }
+                        results.push((i, result));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("manage thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut batch = BatchSummary::default();
+    for (i, result) in results {
+        let file = files[i].clone();
+        match result {
+            Ok(summary) => batch.managed.push(summary),
+            Err(report) => {
+                let report = report.attach_printable(format!(
+                    "failed to manage `{}`",
+                    file.display()
+                ));
+                batch.failed.push((file, report));
+            }
+        }
+    }
+    batch
+}
+
+/// Paths of files to manage, one per line like the output of `find`.
+/// Blank lines are skipped
+pub fn read_file_list<R: BufRead>(reader: R) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if !line.trim().is_empty() {
+            files.push(PathBuf::from(line));
+        }
+    }
+    Ok(files)
+}
+
+/// Decompress, normalize the output directory and write the manifest,
+/// then do the compressed file action. A failure before the action leaves
+/// the compressed file where it was, and a fatal post-decompress failure
+/// after it moves the file back
+pub fn run(
+    cfg: &Cfg,
+    chewwy_root: Option<&Path>,
+    options: ManageOptions,
+) -> StackResult<ManageSummary, ManageError> {
+    let Some(chewwy_root) = chewwy_root else {
+        return Err(crate::NoChewwyRoot).change_context(ManageError);
+    };
+    let manage_cfg = cfg.commands.c().manage.c();
+    let mut directories_cfg = options.directories;
+    directories_cfg.struct_merge(manage_cfg.directories.c());
+    let directories_cfg = directories_cfg.to_absolute(chewwy_root);
+    for overlap in directories_cfg.overlaps() {
+        log::warn!("{overlap}");
+    }
+    if *manage_cfg.confine_directories.c() {
+        if let Some(dir) = directories_cfg.find_outside(chewwy_root) {
+            return Err(ManageError).attach_printable_lazy(|| {
+                format!(
+                    "directory `{}` is outside the chewwy root `{}` and \
+                     `commands.manage.confine-directories` is on",
+                    dir.display(),
+                    chewwy_root.display()
+                )
+            });
+        }
+    }
+    let formats_cfg = cfg.formats.c();
+    let quiet = *cfg.options.c().quiet.c();
+    let messages =
+        utils::Messages::new(quiet).stderr(options.messages_to_stderr);
+    let case_sensitive_extensions =
+        *cfg.options.c().case_sensitive_extensions.c();
+    let dry_run = options.dry_run;
+    let create_missing_dirs = *manage_cfg.create_missing_dirs.c() && !dry_run;
+    let mut file = options.file;
+
+    if file.is_none() {
+        if !manage_cfg.search_file.c() {
+            return Err(ManageError).attach_printable(
+                "no file given, pass one or set \
+                 `commands.manage.search-file = true` to choose one from \
+                 `commands.manage.directories.search`",
+            );
+        }
+        let Some(search_dir) = directories_cfg.search.c() else {
+            return Err(ManageError).attach_printable(
+                "no file given and `commands.manage.directories.search` is \
+                 not set, pass a file or `--search-dir`",
+            );
+        };
+        let search_dir_canon = match search_dir.canonicalize() {
+            Ok(o) => o,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(ManageError).attach_printable_lazy(|| {
+                    format!(
+                        "search directory `{}` of \
+                         `commands.manage.directories.search` is not found",
+                        search_dir.display()
+                    )
+                })
+            }
+            Err(e) => {
+                return Err(e)
+                    .change_context(ManageError)
+                    .attach_printable("search dir")
+            }
+        };
+
+        let search_ignore = SearchIgnore::new(manage_cfg.search_ignore.c())
+            .change_context(ManageError)
+            .attach_printable(
+                "invalid pattern in `commands.manage.search-ignore`",
+            )?;
+        let search = SearchListing {
+            ignore: search_ignore,
+            follow_symlinks: options.follow_symlinks
+                || *manage_cfg.follow_symlinks.c(),
+            since: options.since,
+            now: time::SystemTime::now(),
+            depth: options.search_depth.unwrap_or(*manage_cfg.search_depth.c()),
+            max_entries: options
+                .max_entries
+                .unwrap_or(*manage_cfg.search_max_entries.c()),
+        };
+        let mut items = search.list(&search_dir_canon)?;
+        let sort = options.sort.unwrap_or(*manage_cfg.search_sort.c());
+        sort_items(&mut items, sort);
+        if items.is_empty() {
+            return Err(ManageError).attach_printable_lazy(|| {
+                format!(
+                    "no item found in search directory `{}`, some may be left \
+                     out by `commands.manage.search-ignore`",
+                    search_dir.display()
+                )
+            });
+        }
+        let is_terminal = if messages.stderr {
+            io::stderr().is_terminal()
+        } else {
+            io::stdout().is_terminal()
+        };
+        let color = cfg.options.c().color.c().enabled(is_terminal);
+        file = Some(select_item(items, options.selection, messages, color)?);
+    }
+
+    let compressed_file = file.unwrap();
+    // The compressed file action acts on the given path so a symlink is
+    // moved instead of its target, decompression reads the target
+    let compressed_file_path = path::absolute(&compressed_file)
+        .change_context(ManageError)
+        .attach_printable("cannot make path absolute")?;
+    let canon_compressed_file_path = match compressed_file_path.canonicalize() {
+        Ok(o) => o,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(ManageError).attach_printable_lazy(|| {
+                format!("file `{}` does not exist", compressed_file.display())
+            })
+        }
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(e).change_context(ManageError).attach_printable_lazy(
+                || {
+                    format!(
+                        "permission denied accessing `{}`",
+                        compressed_file.display()
+                    )
+                },
+            )
+        }
+        Err(e) => {
+            return Err(e)
+                .change_context(ManageError)
+                .attach_printable("cannot canonicalize")
+        }
+    };
+    if !canon_compressed_file_path.is_file() {
+        return Err(ManageError).attach_printable_lazy(|| {
+            format!("{} is not a file", compressed_file.display())
+        });
+    }
+    if *manage_cfg.verify_checksum.c() {
+        verify_sidecar_checksum(
+            &compressed_file_path,
+            *manage_cfg.require_checksum.c(),
+        )?;
+    }
+    let compressed_file_name =
+        compressed_file_path.file_name().expect("file name");
+    let now = time::SystemTime::now();
+    let render_name = |template: &str, option: &str| {
+        render_name_template(template, Path::new(compressed_file_name), now)
+            .map_err(|name| {
+                error_stack::Report::new(ManageError).attach_printable(format!(
+                    "unknown placeholder `{{{name}}}` in `commands.manage.{option}`"
+                ))
+            })
+    };
+    let output_file_dir_name =
+        render_name(manage_cfg.output_dir_template.c(), "output-dir-template")?;
+    let output_file_dir_path;
+    let mut partial_output_dir = None;
+    let mut decompressed_by = None;
+    let mut summary = ManageSummary {
+        dry_run,
+        ..Default::default()
+    };
+
+    let mut file_archiver = FileArchiver::new(formats_cfg)
+        .chewwy_root(Some(chewwy_root))
+        .directories(&directories_cfg)
+        .progress(!quiet && io::stderr().is_terminal())
+        .buffer_output(options.buffer_output)
+        .stdout_to_stderr(options.messages_to_stderr)
+        .case_sensitive_extensions(case_sensitive_extensions)
+        .redact(cfg.options.c().redact.c().clone());
+    if let Some(runner) = &options.runner {
+        file_archiver = file_archiver.runner(runner.clone());
+    }
+    let forced_format = options.format.as_deref();
+    if let Some(format_name) = forced_format {
+        file_archiver
+            .named_format(format_name)
+            .change_context(ManageError)?;
+    }
+    let password = match options.password {
+        Some(password) => Some(password),
+        None if dry_run => None,
+        None => {
+            let format = find_format(
+                &file_archiver,
+                &canon_compressed_file_path,
+                forced_format,
+            )?;
+            match format {
+                Some((format_name, format))
+                    if format.needs_password.0 == Some(true) =>
+                {
+                    if options.selection != Selection::Prompt {
+                        return Err(ManageError).attach_printable_lazy(|| {
+                            format!(
+                                "format {format_name} needs a password \
+                                 (`formats.{format_name}.needs-password`), \
+                                 pass `--password` or `--password-file`"
+                            )
+                        });
+                    }
+                    Some(prompt_password(compressed_file_name)?)
+                }
+                _ => None,
+            }
+        }
+    };
+    let file_archiver = file_archiver.password(password);
+
+    let output_file_action = options
+        .output_file_action
+        .unwrap_or(*manage_cfg.output_file_action.c());
+    match output_file_action {
+        cfg::OutputFileAction::DecompressToOutputDir => {
+            if let Some(to) = options.to {
+                let parent = to.parent().filter(|p| !p.as_os_str().is_empty());
+                if let Some(parent) = parent.filter(|_| !dry_run) {
+                    fs::create_dir_all(parent)
+                        .change_context(ManageError)
+                        .attach_printable_lazy(|| {
+                            format!(
+                                "can't create directory `{}`",
+                                parent.display()
+                            )
+                        })?;
+                }
+                output_file_dir_path = Some(to);
+            } else {
+                let Some(output_dir) = directories_cfg.output.c() else {
+                    return Err(ManageError).attach_printable(
+                        "`commands.manage.directories.output` is not set, \
+                         pass `--output-dir` or `--to`",
+                    );
+                };
+                if create_missing_dirs {
+                    create_missing_dir(output_dir, "output")?;
+                }
+                output_file_dir_path =
+                    Some(Path::new(output_dir).join(output_file_dir_name));
+            }
+            if *manage_cfg.space_check.c() && !options.skip_space_check {
+                check_free_space(
+                    &canon_compressed_file_path,
+                    output_file_dir_path.as_ref().unwrap(),
+                    *manage_cfg.space_ratio.c(),
+                    *manage_cfg.min_free_space.c(),
+                )?;
+            }
+            let format_name = if dry_run {
+                find_format(
+                    &file_archiver,
+                    &canon_compressed_file_path,
+                    forced_format,
+                )?
+                .map(|(format_name, _)| format_name.clone())
+            } else {
+                let output_dir = output_file_dir_path.as_ref().unwrap();
+                // an existing directory is decompressed into as is
+                if output_dir.symlink_metadata().is_err() {
+                    partial_output_dir =
+                        Some(PartialOutputDir::new(output_dir));
+                }
+                let decompress_dir = partial_output_dir
+                    .as_ref()
+                    .map_or(output_dir, |partial| &partial.path);
+                let decompressed = match forced_format {
+                    Some(format_name) => file_archiver
+                        .decompress_to_dir_with_format(
+                            &canon_compressed_file_path,
+                            decompress_dir,
+                            format_name,
+                        ),
+                    None => file_archiver.decompress_to_dir(
+                        &canon_compressed_file_path,
+                        decompress_dir,
+                    ),
+                };
+                let decompressed = decompressed
+                    .change_context(ManageError)
+                    .attach_printable("cannont decompress")?;
+                let format_name = decompressed.format.clone();
+                decompressed_by = Some(decompressed);
+                Some(format_name)
+            };
+            summary.format = format_name;
+        }
+        cfg::OutputFileAction::ToStdout => {
+            output_file_dir_path = None;
+            summary.format = if dry_run {
+                find_format(
+                    &file_archiver,
+                    &canon_compressed_file_path,
+                    forced_format,
+                )?
+                .map(|(format_name, _)| format_name.clone())
+            } else {
+                let decompressed = match forced_format {
+                    Some(format_name) => file_archiver
+                        .decompress_to_stdout_with_format(
+                            &canon_compressed_file_path,
+                            format_name,
+                        ),
+                    None => file_archiver
+                        .decompress_to_stdout(&canon_compressed_file_path),
+                };
+                Some(
+                    decompressed
+                        .change_context(ManageError)
+                        .attach_printable("cannont decompress to stdout")?,
+                )
+            };
+        }
+    }
+
+    if let Some(output_file_dir_path) = output_file_dir_path {
+        summary.output_dir = if dry_run {
+            Some(output_file_dir_path)
+        } else {
+            let decompressed = partial_output_dir
+                .as_ref()
+                .map_or(&output_file_dir_path, |partial| &partial.path);
+            let normalized = normalize_output_dir(
+                manage_cfg,
+                decompressed.clone(),
+                messages,
+                &mut summary,
+            )?;
+            match (partial_output_dir, normalized) {
+                (Some(partial), Some(normalized))
+                    if normalized == partial.path =>
+                {
+                    partial
+                        .publish(&output_file_dir_path)
+                        .change_context(ManageError)
+                        .attach_printable_lazy(|| {
+                            format!(
+                                "can't move the decompressed directory to `{}`",
+                                output_file_dir_path.display()
+                            )
+                        })?;
+                    Some(output_file_dir_path)
+                }
+                // the partial directory was removed or unwrapped
+                (_, normalized) => normalized,
+            }
+        };
+    }
+    if let (Some(output), Some(decompressed)) =
+        (&summary.output_dir, &decompressed_by)
+    {
+        if *manage_cfg.write_manifest.c() {
+            write_manifest(
+                output,
+                &compressed_file_path,
+                decompressed,
+                now,
+                summary.unnested,
+            )?;
+        }
+    }
+
+    let compressed_file_action = match options.move_to {
+        Some(path) => cfg::CompressedFileAction::MoveToDir { path },
+        None => manage_cfg.compressed_file_action.c().clone(),
+    };
+    let mut moved_archive = None;
+    match compressed_file_action {
+        cfg::CompressedFileAction::MoveToArchiveDir => {
+            let Some(archive_dir) = directories_cfg.archive.c() else {
+                return Err(ManageError).attach_printable(
+                    "`commands.manage.directories.archive` is not set, pass \
+                     `--archive-dir` or `--move-to`",
+                );
+            };
+            if create_missing_dirs {
+                create_missing_dir(archive_dir, "archive")?;
+            }
+
+            let archive_name = render_name(
+                manage_cfg.archive_name_template.c(),
+                "archive-name-template",
+            )?;
+            let new_path = archive_path(
+                archive_dir.join(archive_name),
+                manage_cfg.archive_on_exists.c(),
+            )?;
+            if !dry_run {
+                log::info!(
+                    "moving `{}` to `{}`",
+                    compressed_file_path.display(),
+                    new_path.display()
+                );
+                utils::move_file(&compressed_file_path, &new_path)
+                    .change_context(ManageError)
+                    .attach_printable_lazy(|| {
+                        format!(
+                            "can't move the archive to `{}`",
+                            archive_dir.display()
+                        )
+                    })?;
+                moved_archive = Some(new_path.clone());
+            }
+            summary.archive = Some(new_path);
+        }
+        cfg::CompressedFileAction::MoveToDir { path } => {
+            let dir = chewwy_root.join(path);
+            let new_path = archive_path(
+                dir.join(compressed_file_name),
+                manage_cfg.archive_on_exists.c(),
+            )?;
+            if !dry_run {
+                fs::create_dir_all(&dir)
+                    .change_context(ManageError)
+                    .attach_printable_lazy(|| {
+                        format!("can't create directory `{}`", dir.display())
+                    })?;
+                log::info!(
+                    "moving `{}` to `{}`",
+                    compressed_file_path.display(),
+                    new_path.display()
+                );
+                utils::move_file(&compressed_file_path, &new_path)
+                    .change_context(ManageError)
+                    .attach_printable_lazy(|| {
+                        format!("can't move the archive to `{}`", dir.display())
+                    })?;
+                moved_archive = Some(new_path.clone());
+            }
+            summary.archive = Some(new_path);
+        }
+        cfg::CompressedFileAction::DoNothing => {}
+    }
+
+    let post_decompress = options
+        .after_cmd
+        .as_ref()
+        .unwrap_or(manage_cfg.post_decompress.c());
+    if !post_decompress.is_empty() {
+        if dry_run {
+            log::info!("skipping post-decompress command in dry run");
+        } else {
+            let archive =
+                summary.archive.as_ref().unwrap_or(&compressed_file_path);
+            let result = run_post_decompress(
+                post_decompress,
+                summary.output_dir.as_deref(),
+                archive,
+                messages,
+            );
+            match result {
+                Err(report) if !manage_cfg.post_decompress_fatal.c() => {
+                    log::warn!("{report:?}");
+                }
+                Err(report) => {
+                    return Err(match &moved_archive {
+                        Some(moved) => undo_archive_move(
+                            report,
+                            moved,
+                            &compressed_file_path,
+                        ),
+                        None => report,
+                    });
+                }
+                Ok(()) => {}
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// The format named `forced_format`, or else the one matching `file`
+fn find_format<'a>(
+    file_archiver: &'a FileArchiver,
+    file: &Path,
+    forced_format: Option<&str>,
+) -> StackResult<Option<(&'a String, &'a cfg::Format)>, ManageError> {
+    match forced_format {
+        Some(format_name) => file_archiver
+            .named_format(format_name)
+            .map(Some)
+            .change_context(ManageError),
+        None => file_archiver.find_format(file).change_context(ManageError),
+    }
+}
+
+/// A directory next to the output directory to decompress into, so a failed
+/// decompression leaves nothing behind. Removed when dropped unless it was
+/// published as the output directory
+struct PartialOutputDir {
+    path: PathBuf,
+    published: bool,
+}
+
+impl PartialOutputDir {
+    /// Left for the decompress command to create, like the output directory
+    fn new(output_dir: &Path) -> PartialOutputDir {
+        PartialOutputDir {
+            path: utils::unused_sibling_path(output_dir, "partial"),
+            published: false,
+        }
+    }
+
+    /// Rename to `output_dir` if it still doesn't exist
+    fn publish(mut self, output_dir: &Path) -> io::Result<()> {
+        if output_dir.symlink_metadata().is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "appeared while decompressing",
+            ));
+        }
+        fs::rename(&self.path, output_dir)?;
+        self.published = true;
+        Ok(())
+    }
+}
+
+impl Drop for PartialOutputDir {
+    fn drop(&mut self) {
+        if self.published {
+            return;
+        }
+        match fs::remove_dir_all(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!(
+                "can't remove partial output `{}`: {e}",
+                self.path.display()
+            ),
+        }
+    }
+}
+
+/// Normalize the decompressed directory by what it contains, recording
+/// the layout in `summary`. Returns where the output is now, `None` when
+/// an empty directory was removed
+///
+/// - Single directory: unnested with `smart-decompress-directory`
+/// - Single file: per `single-file-output`
+/// - Empty: per `empty-output`
+/// - Flat: kept as is
+fn normalize_output_dir(
+    manage_cfg: &cfg::ManageCommandCfg,
+    output_dir: PathBuf,
+    messages: utils::Messages,
+    summary: &mut ManageSummary,
+) -> StackResult<Option<PathBuf>, ManageError> {
+    let layout = utils::dir_layout(&output_dir)
+        .change_context(ManageError)
+        .attach_printable_lazy(|| {
+            format!("can't read `{}`", output_dir.display())
+        })?;
+    log::debug!("`{}` layout is {layout:?}", output_dir.display());
+    match layout {
+        utils::DirLayout::Empty => {
+            summary.layout = Some(OutputLayout::Empty);
+            match manage_cfg.empty_output.c() {
+                cfg::EmptyOutput::Keep => {
+                    log::warn!("nothing was decompressed");
+                }
+                cfg::EmptyOutput::Remove => {
+                    log::warn!("nothing was decompressed, removing it");
+                    fs::remove_dir(&output_dir)
+                        .change_context(ManageError)
+                        .attach_printable_lazy(|| {
+                            format!("can't remove `{}`", output_dir.display())
+                        })?;
+                    return Ok(None);
+                }
+                cfg::EmptyOutput::Error => {
+                    return Err(ManageError).attach_printable_lazy(|| {
+                        format!(
+                            "nothing was decompressed to `{}`",
+                            output_dir.display()
+                        )
+                    });
+                }
+            }
+        }
+        utils::DirLayout::SingleDir(_) => {
+            summary.layout = Some(OutputLayout::SingleDir);
+            if *manage_cfg.smart_decompress_directory.c() {
+                messages.say("Unnesting dir");
+                let levels = utils::unnest_dir_recursive(
+                    &output_dir,
+                    *manage_cfg.smart_decompress_depth.c(),
+                )
+                .change_context(ManageError)
+                .attach_printable("error unnesting dir")?;
+                summary.unnested = levels > 0;
+            }
+        }
+        utils::DirLayout::SingleFile(file) => {
+            summary.layout = Some(OutputLayout::SingleFile);
+            if *manage_cfg.single_file_output.c()
+                == cfg::SingleFileOutput::Unwrap
+            {
+                let unwrapped =
+                    output_dir.with_file_name(file.file_name().expect("name"));
+                if unwrapped != output_dir
+                    && unwrapped.symlink_metadata().is_ok()
+                {
+                    return Err(ManageError).attach_printable_lazy(|| {
+                        format!(
+                            "can't unwrap the only file, `{}` exists",
+                            unwrapped.display()
+                        )
+                    });
+                }
+                let temp = utils::unused_sibling_path(&output_dir, "unwrap");
+                fs::rename(&file, &temp)
+                    .and_then(|()| fs::remove_dir(&output_dir))
+                    .and_then(|()| fs::rename(&temp, &unwrapped))
+                    .change_context(ManageError)
+                    .attach_printable_lazy(|| {
+                        format!("can't unwrap `{}`", file.display())
+                    })?;
+                return Ok(Some(unwrapped));
+            }
+        }
+        utils::DirLayout::Flat => {
+            summary.layout = Some(OutputLayout::Flat);
+        }
+    }
+    Ok(Some(output_dir))
+}
+
+/// Error if the filesystem of `output_dir` can't hold `file` decompressed,
+/// estimated as `ratio` times its size, and still have `min_free_space`
+fn check_free_space(
+    file: &Path,
+    output_dir: &Path,
+    ratio: f64,
+    min_free_space: u64,
+) -> StackResult<(), ManageError> {
+    let compressed_size = fs::metadata(file)
+        .change_context(ManageError)
+        .attach_printable_lazy(|| {
+            format!("can't read the size of `{}`", file.display())
+        })?
+        .len();
+    let required = ((compressed_size as f64 * ratio) as u64)
+        .saturating_add(min_free_space);
+    let available = match utils::available_space(output_dir) {
+        Ok(available) => available,
+        Err(e) => {
+            log::warn!(
+                "can't check free space for `{}`: {e}",
+                output_dir.display()
+            );
+            return Ok(());
+        }
+    };
+    log::debug!("{required} bytes required, {available} bytes available");
+    if available < required {
+        return Err(ManageError).attach_printable_lazy(|| {
+            format!(
+                "not enough space to decompress `{}` to `{}`, needs about \
+                 {required} bytes but {available} are available",
+                file.display(),
+                output_dir.display()
+            )
+        });
+    }
+    Ok(())
+}
+
+/// Create a configured directory named `name` if it doesn't exist yet
+fn create_missing_dir(dir: &Path, name: &str) -> StackResult<(), ManageError> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    log::info!("creating {name} directory `{}`", dir.display());
+    fs::create_dir_all(dir)
+        .change_context(ManageError)
+        .attach_printable_lazy(|| {
+            format!("can't create {name} directory `{}`", dir.display())
+        })
+}
+
+/// Where to move the archive to instead of replacing what's at `path`,
+/// per `archive-on-exists`
+fn archive_path(
+    path: PathBuf,
+    on_exists: &cfg::OnExists,
+) -> StackResult<PathBuf, ManageError> {
+    if path.symlink_metadata().is_err() {
+        return Ok(path);
+    }
+    match on_exists {
+        cfg::OnExists::Number => Ok(utils::unused_numbered_path(&path)),
+        cfg::OnExists::Error => Err(ManageError).attach_printable_lazy(|| {
+            format!(
+                "`{}` already exists, set \
+                     `commands.manage.archive-on-exists = \"number\"` to \
+                     number the archive instead",
+                path.display()
+            )
+        }),
+    }
+}
+
+/// Move the compressed file back from `moved` after a later step failed so
+/// it can be managed again
+fn undo_archive_move(
+    report: error_stack::Report<ManageError>,
+    moved: &Path,
+    original: &Path,
+) -> error_stack::Report<ManageError> {
+    log::info!(
+        "moving `{}` back to `{}`",
+        moved.display(),
+        original.display()
+    );
+    match utils::move_file(moved, original) {
+        Ok(()) => report,
+        Err(e) => report.attach_printable(format!(
+            "can't move the archive back from `{}`: {e}",
+            moved.display()
+        )),
+    }
+}
+
+/// Spawn `argv` with `{OUTPUT_DIR}` and `{ARCHIVE}` substituted and wait
+/// for it. Its stdout is discarded when quiet
+fn run_post_decompress(
+    argv: &[String],
+    output_dir: Option<&Path>,
+    archive: &Path,
+    messages: utils::Messages,
+) -> StackResult<(), ManageError> {
+    let output_dir = output_dir
+        .map(|dir| dir.to_string_lossy())
+        .unwrap_or_default();
+    let archive = archive.to_string_lossy();
+    let render = |arg: &str| {
+        utils::render_template(arg, |name| match name {
+            "OUTPUT_DIR" => Some(&output_dir),
+            "ARCHIVE" => Some(&archive),
+            _ => None,
+        })
+    };
+    let mut command = process::Command::new(render(&argv[0]));
+    command.args(argv[1..].iter().map(|arg| render(arg)));
+    if messages.quiet {
+        command.stdout(process::Stdio::null());
+    } else if messages.stderr {
+        command.stdout(io::stderr());
+    }
+    messages.say("Running post-decompress command");
+    log::debug!("spawning {command:?}");
+    let status = command
+        .status()
+        .change_context(ManageError)
+        .attach_printable_lazy(|| format!("cannot run {command:?}"))?;
+    if !status.success() {
+        return Err(ManageError).attach_printable_lazy(|| {
+            format!("post-decompress command {command:?} failed with {status}")
+        });
+    }
+    Ok(())
+}
+
+/// How the search directory is listed
+struct SearchListing {
+    ignore: SearchIgnore,
+    follow_symlinks: bool,
+    since: Option<time::Duration>,
+    now: time::SystemTime,
+    depth: usize,
+    max_entries: usize,
+}
+
+impl SearchListing {
+    /// The items in `search_dir` and its subdirectories up to `depth`
+    /// levels, which are searched instead of listed. Entries that can't be
+    /// read are left out with a warning, only failing to read `search_dir`
+    /// is an error
+    fn list(
+        &self,
+        search_dir: &Path,
+    ) -> StackResult<Vec<(PathBuf, fs::Metadata)>, ManageError> {
+        let mut items = vec![];
+        let mut skipped = vec![];
+        let mut dirs = vec![(search_dir.to_path_buf(), 0)];
+        while let Some((dir, level)) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if level == 0 => {
+                    return Err(e)
+                        .change_context(ManageError)
+                        .attach_printable("cannot read search dir")
+                }
+                Err(e) => {
+                    skipped
+                        .push(format!("cannot read `{}`: {e}", dir.display()));
+                    continue;
+                }
+            };
+            for entry in entries {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        skipped.push(format!("cannot read entry: {e}"));
+                        continue;
+                    }
+                };
+                if self.ignore.is_ignored(&entry.file_name()) {
+                    log::debug!("ignoring `{}`", entry.path().display());
+                    continue;
+                }
+                let path = entry.path();
+                // the target of a symlink, or the link itself when broken
+                let read = entry.file_type().and_then(|file_type| {
+                    let metadata =
+                        fs::metadata(&path).or_else(|_| entry.metadata());
+                    Ok((file_type.is_symlink(), metadata?))
+                });
+                let (is_symlink, metadata) = match read {
+                    Ok(read) => read,
+                    Err(e) => {
+                        skipped.push(format!(
+                            "cannot read `{}`: {e}",
+                            path.display()
+                        ));
+                        continue;
+                    }
+                };
+                if is_symlink && metadata.is_dir() && !self.follow_symlinks {
+                    log::debug!("skipping symlinked dir `{}`", path.display());
+                    continue;
+                }
+                if metadata.is_dir() && level < self.depth {
+                    dirs.push((path, level + 1));
+                    continue;
+                }
+                if let Some(since) = self.since {
+                    if !is_modified_since(&metadata, since, self.now) {
+                        log::debug!("skipping older `{}`", path.display());
+                        continue;
+                    }
+                }
+                if items.len() == self.max_entries {
+                    return Err(ManageError).attach_printable_lazy(|| {
+                        format!(
+                            "too many entries in search directory `{}`, more \
+                             than {}. Narrow the search with \
+                             `commands.manage.search-ignore` or `--since`, or \
+                             raise `commands.manage.search-max-entries`",
+                            search_dir.display(),
+                            self.max_entries
+                        )
+                    });
+                }
+                items.push((path, metadata));
+            }
+        }
+        for reason in &skipped {
+            log::warn!("{reason}");
+        }
+        if !skipped.is_empty() {
+            log::warn!(
+                "skipped {} unreadable entries of the search directory",
+                skipped.len()
+            );
+        }
+        Ok(items)
+    }
+}
+
+/// File names left out of the search listing
+pub(crate) struct SearchIgnore {
+    patterns: Vec<glob::Pattern>,
+}
+
+impl SearchIgnore {
+    pub(crate) fn new(
+        patterns: &[String],
+    ) -> Result<SearchIgnore, glob::PatternError> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(SearchIgnore { patterns })
+    }
+
+    pub(crate) fn is_ignored(&self, file_name: &OsStr) -> bool {
+        let file_name = file_name.to_string_lossy();
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.matches(&file_name))
+    }
+}
+
+/// Provenance of an extraction, written to `MANIFEST_FILE_NAME` in the
+/// output directory with `commands.manage.write-manifest`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    /// File name of the compressed file
+    pub archive: String,
+    /// Of the compressed file
+    pub sha256: String,
+    pub format: String,
+    /// The decompress command that succeeded, with sensitive values hidden
+    pub command: String,
+    /// UTC time like `2024-12-31T12:30:05Z`
+    pub extracted_at: String,
+    /// Whether a single nested directory was collapsed
+    pub unnested: bool,
+}
+
+/// Next to the output when it's a single file instead of a directory,
+/// prefixed with its name
+pub const MANIFEST_FILE_NAME: &str = ".chewwy-manifest.toml";
+
+fn write_manifest(
+    output: &Path,
+    compressed_file: &Path,
+    decompressed: &file_archiver::Decompressed,
+    now: time::SystemTime,
+    unnested: bool,
+) -> StackResult<(), ManageError> {
+    let sha256 =
+        utils::file_checksum(compressed_file, utils::ChecksumAlgorithm::Sha256)
+            .change_context(ManageError)
+            .attach_printable_lazy(|| {
+                format!("can't checksum `{}`", compressed_file.display())
+            })?;
+    let secs = now
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day, hour, minute, second) =
+        utils::utc_from_unix_secs(secs);
+    let manifest = Manifest {
+        archive: compressed_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        sha256,
+        format: decompressed.format.clone(),
+        command: decompressed.command.clone(),
+        extracted_at: format!(
+            "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z"
+        ),
+        unnested,
+    };
+    let path = if output.is_dir() {
+        output.join(MANIFEST_FILE_NAME)
+    } else {
+        let mut name = output.file_name().unwrap_or_default().to_owned();
+        name.push(MANIFEST_FILE_NAME);
+        output.with_file_name(name)
+    };
+    let content = toml::to_string(&manifest).expect("serialize manifest");
+    fs::write(&path, content)
+        .change_context(ManageError)
+        .attach_printable_lazy(|| {
+            format!("can't write the manifest `{}`", path.display())
+        })
+}
+
+/// Verify `file` against the first sidecar checksum found next to it
+fn verify_sidecar_checksum(
+    file: &Path,
+    require_checksum: bool,
+) -> StackResult<(), ManageError> {
+    for &algorithm in utils::ChecksumAlgorithm::ALL {
+        let mut sidecar = file.as_os_str().to_owned();
+        sidecar.push(".");
+        sidecar.push(algorithm.sidecar_extension());
+        let sidecar = PathBuf::from(sidecar);
+        let expected = match utils::read_sidecar_checksum(&sidecar) {
+            Ok(expected) => expected,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e)
+                    .change_context(ManageError)
+                    .attach_printable_lazy(|| {
+                        format!("cannot read `{}`", sidecar.display())
+                    })
+            }
+        };
+        log::info!("verifying checksum with `{}`", sidecar.display());
+        return utils::verify_checksum(file, &expected, algorithm)
+            .change_context(ManageError)
+            .attach_printable_lazy(|| {
+                format!("failed to verify `{}`", file.display())
+            });
+    }
+    if require_checksum {
+        return Err(ManageError).attach_printable_lazy(|| {
+            format!("no checksum sidecar found for `{}`", file.display())
+        });
+    }
+    log::warn!("no checksum sidecar found for `{}`", file.display());
+    Ok(())
+}
+
+/// Placeholders substituted in `output-dir-template` and
+/// `archive-name-template`
+pub const NAME_PLACEHOLDERS: &[&str] =
+    &["NAME", "EXT", "FILE_NAME", "DATE", "TIME"];
+
+/// Placeholders of `template` that aren't in `NAME_PLACEHOLDERS`
+pub fn unknown_name_placeholders(template: &str) -> Vec<&str> {
+    utils::template_tokens(template)
+        .into_iter()
+        .filter_map(|token| match token {
+            utils::TemplateToken::Placeholder(name)
+                if !NAME_PLACEHOLDERS.contains(&name) =>
+            {
+                Some(name)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Name derived from `file_name` with a template of `NAME_PLACEHOLDERS`,
+/// the first unknown placeholder is the error
+fn render_name_template(
+    template: &str,
+    file_name: &Path,
+    now: time::SystemTime,
+) -> Result<String, String> {
+    if let Some(name) = unknown_name_placeholders(template).first() {
+        return Err(name.to_string());
+    }
+    let file_name_str = file_name.to_string_lossy();
+    let name = file_name.with_extension("");
+    let name = name.to_string_lossy();
+    let ext = file_name.extension().unwrap_or_default().to_string_lossy();
+    let secs = now
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day, hour, minute, second) =
+        utils::utc_from_unix_secs(secs);
+    let date = format!("{year:04}-{month:02}-{day:02}");
+    let time = format!("{hour:02}-{minute:02}-{second:02}");
+    Ok(utils::render_template(
+        template,
+        |placeholder| match placeholder {
+            "NAME" => Some(&name),
+            "EXT" => Some(&ext),
+            "FILE_NAME" => Some(&file_name_str),
+            "DATE" => Some(&date),
+            "TIME" => Some(&time),
+            _ => None,
+        },
+    ))
+}
+
+/// Whether the item was modified at most `since` before `now`. An unknown
+/// or future modified time counts as recent
+fn is_modified_since(
+    metadata: &fs::Metadata,
+    since: time::Duration,
+    now: time::SystemTime,
+) -> bool {
+    let Ok(modified) = metadata.modified() else {
+        return true;
+    };
+    now.duration_since(modified)
+        .map_or(true, |age| age <= since)
+}
+
+/// Order `items` by `key`, ties by path
+fn sort_items(items: &mut [(PathBuf, fs::Metadata)], key: cfg::SortKey) {
+    use std::cmp::Reverse;
+    match key {
+        cfg::SortKey::Name => items.sort_by(|(a, _), (b, _)| a.cmp(b)),
+        cfg::SortKey::ModifiedDesc => items.sort_by_key(|(path, metadata)| {
+            (Reverse(metadata.modified().ok()), path.clone())
+        }),
+        cfg::SortKey::SizeDesc => items.sort_by_key(|(path, metadata)| {
+            (Reverse(metadata.len()), path.clone())
+        }),
+    }
+}
+
+fn select_item(
+    mut items: Vec<(PathBuf, fs::Metadata)>,
+    selection: Selection,
+    messages: utils::Messages,
+    color: bool,
+) -> StackResult<PathBuf, ManageError> {
+    match selection {
+        Selection::Prompt => prompt_item(&items, messages, color),
+        Selection::Single => {
+            if items.len() > 1 {
+                return Err(ManageError).attach_printable_lazy(|| {
+                    format!(
+                        "{} candidates found in search directory, specify a file",
+                        items.len()
+                    )
+                });
+            }
+            Ok(items.remove(0).0)
+        }
+    }
+}
+
+fn prompt_item(
+    items: &[(PathBuf, fs::Metadata)],
+    messages: utils::Messages,
+    color: bool,
+) -> StackResult<PathBuf, ManageError> {
+    // the items are still listed when quiet, they're needed to choose
+    messages.say("Choose an item");
+    for line in render_items(items, color) {
+        messages.print(format_args!("{line}\n"));
+    }
+    messages.print("> ");
+    io::stdout()
+        .flush()
+        .change_context(ManageError)
+        .attach_printable("error flushing")?;
+    let mut input = String::new();
+    let read = io::stdin()
+        .read_line(&mut input)
+        .change_context(ManageError)?;
+    if read == 0 {
+        return Err(error_stack::Report::new(Cancelled))
+            .change_context(ManageError)
+            .attach_printable("no item chosen");
+    }
+    let paths = items
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    let index =
+        parse_selection(input.trim(), &paths).change_context(ManageError)?;
+    Ok(paths[index].clone())
+}
+
+/// Lines listing `items` to choose from as `[index] name size modified`,
+/// with aligned columns. Directories have `-` as size, like an unknown
+/// modified time. With `color`, directory names are blue and the size and
+/// modified time dim
+pub fn render_items(
+    items: &[(PathBuf, fs::Metadata)],
+    color: bool,
+) -> Vec<String> {
+    let paint = |text: String, code: &str| {
+        if color {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text
+        }
+    };
+    let columns = items
+        .iter()
+        .map(|(item, metadata)| {
+            let name = item
+                .file_name()
+                .unwrap_or_else(|| OsStr::new("???"))
+                .to_string_lossy()
+                .to_string();
+            let is_dir = metadata.is_dir();
+            let size = if is_dir {
+                "-".to_string()
+            } else {
+                utils::human_size(metadata.len())
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|m| m.duration_since(time::UNIX_EPOCH).ok())
+                .map_or_else(
+                    || "-".to_string(),
+                    |since_epoch| {
+                        let (year, month, day, hour, minute, _) =
+                            utils::utc_from_unix_secs(since_epoch.as_secs());
+                        format!(
+                            "{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}"
+                        )
+                    },
+                );
+            (name, is_dir, size, modified)
+        })
+        .collect::<Vec<_>>();
+    let index_width = items.len().saturating_sub(1).to_string().len();
+    let name_width = columns
+        .iter()
+        .map(|(name, ..)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let size_width = columns
+        .iter()
+        .map(|(_, _, size, _)| size.len())
+        .max()
+        .unwrap_or(0);
+    columns
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, is_dir, size, modified))| {
+            let name = format!("{name:<name_width$}");
+            let name = if is_dir { paint(name, "34") } else { name };
+            format!(
+                "[{i:>index_width$}] {name}  {}  {}",
+                paint(format!("{size:>size_width$}"), "2"),
+                paint(modified, "2"),
+            )
+        })
+        .collect()
+}
+
+/// Ask for the password of `file_name` without echoing it
+#[cfg(feature = "prompt-password")]
+fn prompt_password(file_name: &OsStr) -> StackResult<String, ManageError> {
+    let prompt = format!("Password for {}: ", file_name.to_string_lossy());
+    match rpassword::prompt_password(prompt) {
+        Ok(password) => Ok(password),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            Err(error_stack::Report::new(Cancelled))
+                .change_context(ManageError)
+                .attach_printable("no password given")
+        }
+        Err(e) => Err(e)
+            .change_context(ManageError)
+            .attach_printable("error reading password"),
+    }
+}
+
+#[cfg(not(feature = "prompt-password"))]
+fn prompt_password(file_name: &OsStr) -> StackResult<String, ManageError> {
+    Err(ManageError).attach_printable_lazy(|| {
+        format!(
+            "{} needs a password, pass `--password` or `--password-file`, \
+             prompting needs the `prompt-password` feature",
+            file_name.to_string_lossy()
+        )
+    })
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseSelectionError {
+    #[error("no item exists at index {0}")]
+    IndexOutOfRange(usize),
+    #[error("no item matches \"{0}\"")]
+    NoMatch(String),
+    #[error("\"{input}\" matches multiple items {candidates:?}")]
+    Ambiguous {
+        input: String,
+        candidates: Vec<String>,
+    },
+}
+
+/// Index of the item chosen by `input`: an exact file name, an index,
+/// or a prefix of only one file name, in that order
+fn parse_selection(
+    input: &str,
+    items: &[PathBuf],
+) -> Result<usize, ParseSelectionError> {
+    let file_names = items
+        .iter()
+        .map(|item| {
+            item.file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    if let Some(index) = file_names.iter().position(|name| name == input) {
+        return Ok(index);
+    }
+    if let Ok(index) = input.parse::<usize>() {
+        if index >= items.len() {
+            return Err(ParseSelectionError::IndexOutOfRange(index));
+        }
+        return Ok(index);
+    }
+    let matches = file_names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !input.is_empty() && name.starts_with(input))
+        .collect::<Vec<_>>();
+    match matches[..] {
+        [] => Err(ParseSelectionError::NoMatch(input.to_string())),
+        [(index, _)] => Ok(index),
+        _ => Err(ParseSelectionError::Ambiguous {
+            input: input.to_string(),
+            candidates: matches
+                .into_iter()
+                .map(|(_, name)| name.to_string())
+                .collect(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// `toml` over the default config
+    fn cfg(toml: &str) -> Cfg {
+        let mut cfg = toml::from_str::<Cfg>(toml).unwrap();
+        cfg.struct_merge(&Cfg::default());
+        cfg
+    }
+
+    /// A canonicalized temporary chewwy root and an empty `name` in it,
+    /// removed when the `TempDir` is dropped
+    fn root_with_archive(name: &str) -> (TempDir, PathBuf, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        let file = root.join(name);
+        fs::write(&file, "").unwrap();
+        (tmp, root, file)
+    }
+
+    #[test]
+    fn select_item_single() {
+        let tmp = tempfile::tempdir().unwrap();
+        let item = |name| {
+            let path = tmp.path().join(name);
+            fs::write(&path, "").unwrap();
+            let metadata = fs::metadata(&path).unwrap();
+            (path, metadata)
+        };
+        let items = vec![item("a.zip")];
+        assert_eq!(
+            tmp.path().join("a.zip"),
+            select_item(
+                items,
+                Selection::Single,
+                utils::Messages::default(),
+                false
+            )
+            .unwrap()
+        );
+        let items = vec![item("a.zip"), item("b.zip")];
+        assert!(select_item(
+            items,
+            Selection::Single,
+            utils::Messages::default(),
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn is_modified_since_age() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = fs::File::create(tmp.path().join("a.zip")).unwrap();
+        let hour = time::Duration::from_secs(3600);
+        let modified = time::UNIX_EPOCH + 100 * hour;
+        file.set_modified(modified).unwrap();
+        let metadata = file.metadata().unwrap();
+
+        assert!(is_modified_since(&metadata, hour, modified + hour));
+        assert!(!is_modified_since(&metadata, hour, modified + 2 * hour));
+        assert!(is_modified_since(&metadata, hour, modified - hour));
+    }
+
+    #[test]
+    fn sort_items_keys() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut items = [("a", 1, 30), ("b", 3, 10), ("c", 2, 20)]
+            .into_iter()
+            .map(|(name, len, secs)| {
+                let path = tmp.path().join(name);
+                let file = fs::File::create(&path).unwrap();
+                file.set_len(len).unwrap();
+                file.set_modified(
+                    time::UNIX_EPOCH + time::Duration::from_secs(secs),
+                )
+                .unwrap();
+                (path, file.metadata().unwrap())
+            })
+            .collect::<Vec<_>>();
+        let mut sorted = |key| {
+            sort_items(&mut items, key);
+            items
+                .iter()
+                .map(|(path, _)| path.file_name().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(["b", "c", "a"], sorted(cfg::SortKey::SizeDesc)[..]);
+        assert_eq!(["a", "c", "b"], sorted(cfg::SortKey::ModifiedDesc)[..]);
+        assert_eq!(["a", "b", "c"], sorted(cfg::SortKey::Name)[..]);
+    }
+
+    #[test]
+    fn render_items_columns() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("a-dir");
+        fs::create_dir(&dir).unwrap();
+        let big = tmp.path().join("big.zip");
+        let file = fs::File::create(&big).unwrap();
+        file.set_len(1536).unwrap();
+        // 2024-12-31 12:30 UTC
+        let modified = time::UNIX_EPOCH + time::Duration::from_secs(1735648200);
+        file.set_modified(modified).unwrap();
+        let mut items = vec![
+            (big.clone(), fs::metadata(&big).unwrap()),
+            (dir.clone(), fs::metadata(&dir).unwrap()),
+        ];
+        for i in 0..9 {
+            let path = tmp.path().join(format!("{i}"));
+            fs::write(&path, "ab").unwrap();
+            items.push((path.clone(), fs::metadata(&path).unwrap()));
+        }
+
+        let lines = render_items(&items, false);
+        assert_eq!("[ 0] big.zip  1.5 KiB  2024-12-31 12:30", lines[0]);
+        assert!(lines[1].starts_with("[ 1] a-dir          -  "));
+        assert!(lines[10].starts_with("[10] 8            2 B  "));
+
+        let lines = render_items(&items[1..2], true);
+        assert!(
+            lines[0].starts_with("[0] \x1b[34ma-dir\x1b[0m  \x1b[2m-\x1b[0m")
+        );
+    }
+
+    #[test]
+    fn parse_selection_input() {
+        let items = ["a.zip", "ab.zip", "b.tar.gz", "10"]
+            .map(PathBuf::from)
+            .to_vec();
+        assert_eq!(Ok(1), parse_selection("1", &items));
+        assert_eq!(Ok(3), parse_selection("10", &items));
+        assert_eq!(Ok(0), parse_selection("a.zip", &items));
+        assert_eq!(Ok(2), parse_selection("b", &items));
+        assert_eq!(Ok(1), parse_selection("ab", &items));
+        assert_eq!(
+            Err(ParseSelectionError::IndexOutOfRange(4)),
+            parse_selection("4", &items)
+        );
+        assert_eq!(
+            Err(ParseSelectionError::NoMatch("c".to_string())),
+            parse_selection("c", &items)
+        );
+        assert_eq!(
+            Err(ParseSelectionError::NoMatch("".to_string())),
+            parse_selection("", &items)
+        );
+        assert_eq!(
+            Err(ParseSelectionError::Ambiguous {
+                input: "a".to_string(),
+                candidates: vec!["a.zip".to_string(), "ab.zip".to_string()],
+            }),
+            parse_selection("a", &items)
+        );
+    }
+
+    #[test]
+    fn render_name_template_placeholders() {
+        let now = time::UNIX_EPOCH + time::Duration::from_secs(1735648205);
+        let render = |template| {
+            render_name_template(template, Path::new("a.tar.gz"), now)
+        };
+        assert_eq!(
+            Ok("2024-12-31_a.tar.gz".to_string()),
+            render("{DATE}_{FILE_NAME}")
+        );
+        assert_eq!(Ok("a.tar".to_string()), render("{NAME}"));
+        assert_eq!(
+            Ok("gz-a.tar-2024-12-31_12-30-05".to_string()),
+            render("{EXT}-{NAME}-{DATE}_{TIME}")
+        );
+        assert_eq!(Err("FILE".to_string()), render("{NAME}{FILE}"));
+    }
+
+    #[test]
+    fn search_ignore_patterns() {
+        let search_ignore =
+            SearchIgnore::new(&[".*".to_string(), "*.part".to_string()])
+                .unwrap();
+        for ignored in [".DS_Store", ".hidden.zip", "a.zip.part"] {
+            assert!(search_ignore.is_ignored(OsStr::new(ignored)), "{ignored}");
+        }
+        for kept in ["a.zip", "part", "a.part.zip"] {
+            assert!(!search_ignore.is_ignored(OsStr::new(kept)), "{kept}");
+        }
+        assert!(SearchIgnore::new(&["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn run_no_file_hints() {
+        let root = tempfile::tempdir().unwrap();
+        let run = |toml: &str, search: Option<PathBuf>| {
+            let cfg = cfg(toml);
+            let options = ManageOptions {
+                directories: cfg::Directories {
+                    search: cfg::Configure(Some(search)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let report = run(&cfg, Some(root.path()), options).unwrap_err();
+            format!("{report:?}")
+        };
+        let search = || Some(PathBuf::from("search"));
+
+        let report = run("[commands.manage]\nsearch-file = false", search());
+        assert!(
+            report.contains("`commands.manage.search-file = true`"),
+            "{report}"
+        );
+        let report = run("", None);
+        assert!(
+            report.contains("`commands.manage.directories.search` is not set"),
+            "{report}"
+        );
+        let report = run("", search());
+        assert!(
+            report.contains("of `commands.manage.directories.search` is not"),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn run_all_ignored_search_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let search = root.path().join("search");
+        fs::create_dir(&search).unwrap();
+        fs::write(search.join(".DS_Store"), "").unwrap();
+
+        let report = run(
+            &Cfg::default(),
+            Some(root.path()),
+            ManageOptions {
+                dry_run: true,
+                selection: Selection::Single,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(format!("{report:?}").contains("no item found"));
+    }
+
+    #[test]
+    fn run_search_depth_and_max_entries() {
+        let root = tempfile::tempdir().unwrap();
+        let search = root.path().join("search");
+        fs::create_dir_all(search.join("downloads")).unwrap();
+        fs::write(search.join("downloads/a.zip"), "").unwrap();
+        let options = |depth, max_entries| ManageOptions {
+            dry_run: true,
+            selection: Selection::Single,
+            search_depth: Some(depth),
+            max_entries: Some(max_entries),
+            ..Default::default()
+        };
+        let run = |options| run(&Cfg::default(), Some(root.path()), options);
+
+        // the directory itself is the only item, and not a file
+        let report = run(options(0, 10)).unwrap_err();
+        assert!(
+            format!("{report:?}").contains("is not a file"),
+            "{report:?}"
+        );
+
+        let summary = run(options(1, 10)).unwrap();
+        assert_eq!(Some("zip".to_string()), summary.format);
+        assert!(summary.output_dir.unwrap().ends_with("output/a"));
+
+        fs::create_dir(search.join("downloads/nested")).unwrap();
+        fs::write(search.join("downloads/nested/b.zip"), "").unwrap();
+        let report = run(options(2, 2)).unwrap_err();
+        assert!(format!("{report:?}").contains("2 candidates"), "{report:?}");
+
+        let report = run(options(2, 1)).unwrap_err();
+        let report = format!("{report:?}");
+        assert!(report.contains("too many entries"), "{report}");
+        assert!(report.contains("more than 1"), "{report}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_search_symlinked_dirs() {
+        let root = tempfile::tempdir().unwrap();
+        let search = root.path().join("search");
+        fs::create_dir(&search).unwrap();
+        fs::write(search.join("a.zip"), "").unwrap();
+        // links back to the search directory
+        std::os::unix::fs::symlink(&search, search.join("loop")).unwrap();
+        let options = ManageOptions {
+            dry_run: true,
+            selection: Selection::Single,
+            ..Default::default()
+        };
+
+        let summary =
+            run(&Cfg::default(), Some(root.path()), options.clone()).unwrap();
+        assert_eq!(Some("zip".to_string()), summary.format);
+
+        let options = ManageOptions {
+            follow_symlinks: true,
+            ..options
+        };
+        let report =
+            run(&Cfg::default(), Some(root.path()), options).unwrap_err();
+        assert!(format!("{report:?}").contains("2 candidates"));
+    }
+
+    #[test]
+    fn run_batch_keep_going() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("a.zip");
+        fs::write(&file, "").unwrap();
+        let files = vec![root.path().join("missing.zip"), file];
+        let options = ManageOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let cfg = Cfg::default();
+
+        let batch = run_batch(
+            &cfg,
+            Some(root.path()),
+            options.clone(),
+            files.clone(),
+            false,
+            1,
+        );
+        assert_eq!(0, batch.managed.len());
+        assert_eq!(1, batch.failed.len());
+
+        let batch = run_batch(&cfg, Some(root.path()), options, files, true, 1);
+        assert_eq!(1, batch.managed.len());
+        assert_eq!(1, batch.failed.len());
+        assert_eq!(root.path().join("missing.zip"), batch.failed[0].0);
+    }
+
+    #[test]
+    fn read_file_list_lines() {
+        let list = "a.zip\n\n  \nb c.zip\r\n dir/d.tar.gz\n";
+        assert_eq!(
+            vec![
+                PathBuf::from("a.zip"),
+                PathBuf::from("b c.zip"),
+                PathBuf::from(" dir/d.tar.gz"),
+            ],
+            read_file_list(io::Cursor::new(list)).unwrap()
+        );
+        assert!(read_file_list(io::empty()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn run_batch_jobs_keeps_order() {
+        let root = tempfile::tempdir().unwrap();
+        let names = ["a.zip", "b.zip", "c.zip", "d.zip", "e.zip"];
+        let files = names
+            .iter()
+            .map(|name| root.path().join(name))
+            .collect::<Vec<_>>();
+        for file in &files {
+            fs::write(file, "").unwrap();
+        }
+        let options = ManageOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let batch = run_batch(
+            &Cfg::default(),
+            Some(root.path()),
+            options,
+            files,
+            false,
+            3,
+        );
+        assert!(batch.failed.is_empty());
+        let archived = batch
+            .managed
+            .iter()
+            .map(|summary| summary.archive.as_ref().unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names.map(|name| Some(OsStr::new(name))).to_vec(), archived);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_creates_missing_dirs() {
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        let cfg = |create_missing_dirs: bool| {
+            cfg(&format!(
+                r#"
+                [formats.zip]
+                [[formats.zip.decompress]]
+                path = "mkdir"
+                args = ["{{DIR}}"]
+                [commands.manage]
+                create-missing-dirs = {create_missing_dirs}
+                "#,
+            ))
+        };
+        let options = ManageOptions {
+            file: Some(file),
+            ..Default::default()
+        };
+
+        assert!(run(&cfg(false), Some(&root), options.clone()).is_err());
+        assert!(!root.join("output").exists());
+
+        let summary = run(&cfg(true), Some(&root), options).unwrap();
+        assert_eq!(Some(root.join("output/a")), summary.output_dir);
+        assert_eq!(Some(root.join("archive/a.zip")), summary.archive);
+        assert!(root.join("output/a").is_dir());
+        assert!(root.join("archive/a.zip").is_file());
+    }
+
+    #[test]
+    fn run_needs_password() {
+        let (_tmp, root, file) = root_with_archive("a.7z");
+        let cfg = cfg(r#"
+            [formats.7z]
+            extensions = ["7z"]
+            needs-password = true
+            decompress = []
+            "#);
+        let options = ManageOptions {
+            file: Some(file),
+            selection: Selection::Single,
+            ..Default::default()
+        };
+
+        let error = run(&cfg, Some(&root), options.clone()).unwrap_err();
+        assert!(format!("{error:?}").contains("needs a password"));
+
+        let options = ManageOptions {
+            password: Some("pw".to_string()),
+            ..options
+        };
+        let error = run(&cfg, Some(&root), options).unwrap_err();
+        assert!(!format!("{error:?}").contains("needs a password"));
+    }
+
+    #[test]
+    fn normalize_output_dir_layouts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let normalize = |cfg: &Cfg, dir: &Path| {
+            let mut summary = ManageSummary::default();
+            let output_dir = normalize_output_dir(
+                cfg.commands.c().manage.c(),
+                dir.to_path_buf(),
+                utils::Messages::new(true),
+                &mut summary,
+            );
+            (output_dir.map_err(|_| ()), summary)
+        };
+        let default = cfg("");
+
+        // flat extract stays wrapped in its directory
+        let flat = tmp.path().join("flat");
+        fs::create_dir(&flat).unwrap();
+        fs::write(flat.join("a.txt"), "").unwrap();
+        fs::write(flat.join("b.txt"), "").unwrap();
+        let (output_dir, summary) = normalize(&default, &flat);
+        assert_eq!(Ok(Some(flat.clone())), output_dir);
+        assert_eq!(Some(OutputLayout::Flat), summary.layout);
+        assert!(flat.join("a.txt").is_file());
+
+        // wrapped extract is unnested
+        let wrapped = tmp.path().join("wrapped");
+        fs::create_dir_all(wrapped.join("inner")).unwrap();
+        fs::write(wrapped.join("inner/a.txt"), "").unwrap();
+        let (output_dir, summary) = normalize(&default, &wrapped);
+        assert_eq!(Ok(Some(wrapped.clone())), output_dir);
+        assert_eq!(Some(OutputLayout::SingleDir), summary.layout);
+        assert!(summary.unnested);
+        assert!(wrapped.join("a.txt").is_file());
+
+        let single = tmp.path().join("single");
+        fs::create_dir(&single).unwrap();
+        fs::write(single.join("a.txt"), "").unwrap();
+        let (output_dir, summary) = normalize(&default, &single);
+        assert_eq!(Ok(Some(single.clone())), output_dir);
+        assert_eq!(Some(OutputLayout::SingleFile), summary.layout);
+        let unwrap = cfg(r#"
+            [commands.manage]
+            single-file-output = "unwrap"
+            "#);
+        let (output_dir, _) = normalize(&unwrap, &single);
+        assert_eq!(Ok(Some(tmp.path().join("a.txt"))), output_dir);
+        assert!(!single.exists());
+
+        let empty = tmp.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        let error = cfg(r#"
+            [commands.manage]
+            empty-output = "error"
+            "#);
+        assert_eq!(Err(()), normalize(&error, &empty).0);
+        let remove = cfg(r#"
+            [commands.manage]
+            empty-output = "remove"
+            "#);
+        let (output_dir, summary) = normalize(&remove, &empty);
+        assert_eq!(Ok(None), output_dir);
+        assert_eq!(Some(OutputLayout::Empty), summary.layout);
+        assert!(!empty.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_space_check() {
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        fs::write(&file, "not empty").unwrap();
+        let cfg = cfg(r#"
+            [commands.manage]
+            space-ratio = 1e300
+            "#);
+        let options = ManageOptions {
+            file: Some(file),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let error = run(&cfg, Some(&root), options.clone()).unwrap_err();
+        assert!(format!("{error:?}").contains("not enough space"));
+
+        let options = ManageOptions {
+            skip_space_check: true,
+            ..options
+        };
+        assert!(run(&cfg, Some(&root), options).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_moves_symlink() {
+        let (_tmp, root, target) = root_with_archive("real.zip");
+        let link = root.join("link.zip");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        fs::create_dir(root.join("archive")).unwrap();
+        let cfg = cfg(r#"
+            [formats.zip]
+            [[formats.zip.decompress]]
+            path = "sh"
+            args = ["-c", "mkdir -p \"$0\"", "{DIR}"]
+            "#);
+
+        let summary = run(
+            &cfg,
+            Some(&root),
+            ManageOptions {
+                file: Some(link.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let archived = root.join("archive/link.zip");
+        assert_eq!(Some(root.join("output/link")), summary.output_dir);
+        assert_eq!(Some(archived.clone()), summary.archive);
+        assert!(target.is_file());
+        assert!(!link.exists());
+        assert!(archived.symlink_metadata().unwrap().is_symlink());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_post_decompress_placeholders() {
+        let tmp = tempfile::tempdir().unwrap();
+        let marker = tmp.path().join("marker");
+        let argv = [
+            "sh",
+            "-c",
+            "echo \"$0 $1\" > \"$2\"",
+            "{OUTPUT_DIR}",
+            "{ARCHIVE}",
+            marker.to_str().unwrap(),
+        ]
+        .map(String::from);
+        run_post_decompress(
+            &argv,
+            Some(Path::new("out/a")),
+            Path::new("archive/a.zip"),
+            utils::Messages::new(true),
+        )
+        .unwrap();
+        assert_eq!(
+            "out/a archive/a.zip\n",
+            fs::read_to_string(&marker).unwrap()
+        );
+
+        let argv = ["sh", "-c", "exit 1"].map(String::from);
+        assert!(run_post_decompress(
+            &argv,
+            None,
+            Path::new("a.zip"),
+            utils::Messages::new(true)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn run_archive_name_collision() {
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        fs::create_dir(root.join("archive")).unwrap();
+        fs::write(root.join("archive/old-a.zip"), "").unwrap();
+        let numbered = cfg(r#"
+            [commands.manage]
+            archive-name-template = "old-{FILE_NAME}"
+            "#);
+        let options = ManageOptions {
+            file: Some(file),
+            dry_run: true,
+            ..Default::default()
+        };
+
+        let summary = run(&numbered, Some(&root), options.clone()).unwrap();
+        assert_eq!(Some(root.join("archive/old-a-1.zip")), summary.archive);
+
+        let error = cfg(r#"
+            [commands.manage]
+            archive-name-template = "old-{FILE_NAME}"
+            archive-on-exists = "error"
+            "#);
+        assert!(run(&error, Some(&root), options).is_err());
+    }
+
+    #[test]
+    fn run_verify_statuses() {
+        use crate::testing::FakeRunner;
+        let mut cfg = cfg(r#"
+            [formats.zip]
+            [[formats.zip.verify]]
+            path = "7z"
+            args = ["t", "{FILE}"]
+            [formats.rar]
+            [[formats.rar.verify]]
+            path = "unrar"
+            args = ["t", "{FILE}"]
+            "#);
+        // a format without `verify`
+        cfg.formats
+            .0
+            .as_mut()
+            .unwrap()
+            .get_mut("7z")
+            .unwrap()
+            .verify = cfg::Configure(None);
+        let runner =
+            Arc::new(FakeRunner::new().exits("7z", 0).exits("unrar", 2));
+        let options = ManageOptions {
+            runner: Some(runner.clone()),
+            ..Default::default()
+        };
+        let files = ["a.zip", "b.rar", "c.7z", "d.txt"].map(PathBuf::from);
+
+        let verified =
+            run_verify(&cfg, None, &options, files.to_vec()).unwrap();
+        let statuses = verified
+            .iter()
+            .map(|summary| (summary.format.as_deref(), summary.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Some("zip"), VerifyStatus::Ok),
+                (Some("rar"), VerifyStatus::Corrupt),
+                (Some("7z"), VerifyStatus::Failed),
+                (None, VerifyStatus::Failed),
+            ],
+            statuses
+        );
+        assert!(verified[2].error.as_ref().unwrap().contains("`verify`"));
+        assert_eq!(vec!["7z", "unrar"], runner.programs());
+    }
+
+    #[test]
+    fn run_with_fake_runner() {
+        use crate::testing::FakeRunner;
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        // 7z is the first zip command, `x {FILE} -o{DIR}`
+        let runner = Arc::new(
+            FakeRunner::new().extracts("7z", &[("a.txt", "a"), ("b.txt", "b")]),
+        );
+        let options = ManageOptions {
+            file: Some(file.clone()),
+            runner: Some(runner.clone()),
+            ..Default::default()
+        };
+
+        let summary =
+            run(&Cfg::default(), Some(&root), options.clone()).unwrap();
+        assert_eq!(vec!["7z"], runner.programs());
+        assert_eq!(Some(root.join("output/a")), summary.output_dir);
+        assert!(root.join("output/a/b.txt").is_file());
+        assert_eq!(Some(root.join("archive/a.zip")), summary.archive);
+        assert!(root.join("archive/a.zip").is_file());
+        assert!(!file.exists());
+
+        fs::write(&file, "").unwrap();
+        let moved = root.join("moved");
+        let options = ManageOptions {
+            move_to: Some(moved.clone()),
+            ..options
+        };
+        let summary =
+            run(&Cfg::default(), Some(&root), options.clone()).unwrap();
+        assert_eq!(Some(root.join("output/a")), summary.output_dir);
+        assert_eq!(Some(moved.join("a.zip")), summary.archive);
+        assert!(!file.exists());
+
+        // the archive moved before is kept
+        fs::write(&file, "second").unwrap();
+        let summary = run(&Cfg::default(), Some(&root), options).unwrap();
+        assert_eq!(Some(moved.join("a-1.zip")), summary.archive);
+        assert_eq!("", fs::read_to_string(moved.join("a.zip")).unwrap());
+        assert_eq!(
+            "second",
+            fs::read_to_string(moved.join("a-1.zip")).unwrap()
+        );
+    }
+
+    #[test]
+    fn run_write_manifest() {
+        use crate::testing::FakeRunner;
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        let runner = Arc::new(
+            FakeRunner::new().extracts("7z", &[("a.txt", "a"), ("b.txt", "b")]),
+        );
+        let cfg = cfg(r#"
+            [commands.manage]
+            write-manifest = true
+            "#);
+        let options = ManageOptions {
+            file: Some(file.clone()),
+            runner: Some(runner),
+            ..Default::default()
+        };
+
+        run(&cfg, Some(&root), options).unwrap();
+        let manifest = root.join("output/a").join(MANIFEST_FILE_NAME);
+        let manifest = fs::read_to_string(manifest).unwrap();
+        let manifest = toml::from_str::<toml::Table>(&manifest).unwrap();
+        assert_eq!("a.zip", manifest["archive"].as_str().unwrap());
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            manifest["sha256"].as_str().unwrap()
+        );
+        assert_eq!("zip", manifest["format"].as_str().unwrap());
+        let command = manifest["command"].as_str().unwrap();
+        assert!(command.starts_with(r#""7z" "x" "#), "{command}");
+        let extracted_at = manifest["extracted-at"].as_str().unwrap();
+        assert_eq!(20, extracted_at.len(), "{extracted_at}");
+        assert!(extracted_at.ends_with('Z'), "{extracted_at}");
+        assert_eq!(Some(false), manifest["unnested"].as_bool());
+    }
+
+    #[test]
+    fn run_failed_normalize_keeps_archive() {
+        use crate::testing::FakeRunner;
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        // an archive of nothing
+        let runner = Arc::new(FakeRunner::new().extracts("7z", &[]));
+        let cfg = cfg(r#"
+            [commands.manage]
+            empty-output = "error"
+            "#);
+        let options = ManageOptions {
+            file: Some(file.clone()),
+            runner: Some(runner.clone()),
+            ..Default::default()
+        };
+
+        assert!(run(&cfg, Some(&root), options).is_err());
+        assert_eq!(vec!["7z"], runner.programs());
+        assert!(file.is_file());
+        assert!(!root.join("archive/a.zip").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_failed_post_decompress_moves_archive_back() {
+        use crate::testing::FakeRunner;
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        let runner =
+            Arc::new(FakeRunner::new().extracts("7z", &[("a.txt", "a")]));
+        let cfg = cfg(r#"
+            [commands.manage]
+            post-decompress = ["false"]
+            post-decompress-fatal = true
+            "#);
+        let options = ManageOptions {
+            file: Some(file.clone()),
+            runner: Some(runner),
+            ..Default::default()
+        };
+
+        let report = run(&cfg, Some(&root), options).unwrap_err();
+        assert!(format!("{report:?}").contains("failed"), "{report:?}");
+        assert!(file.is_file());
+        assert!(!root.join("archive/a.zip").exists());
+    }
+
+    #[test]
+    fn run_forced_format() {
+        use crate::testing::FakeRunner;
+        let (_tmp, root, file) = root_with_archive("a.tar");
+        let runner = Arc::new(
+            FakeRunner::new().extracts("7z", &[("a.txt", "a"), ("b.txt", "b")]),
+        );
+        let options = |format: &str| ManageOptions {
+            file: Some(file.clone()),
+            runner: Some(runner.clone()),
+            format: Some(format.to_string()),
+            ..Default::default()
+        };
+
+        let error = run(&Cfg::default(), Some(&root), options("nope"));
+        let error = error.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<file_archiver::DecompressError>(),
+            Some(file_archiver::DecompressError::UnknownFormat { format })
+                if format == "nope"
+        ));
+        assert!(runner.programs().is_empty());
+        assert!(file.is_file());
+
+        let summary =
+            run(&Cfg::default(), Some(&root), options("zip")).unwrap();
+        assert_eq!(Some("zip"), summary.format.as_deref());
+        assert_eq!(vec!["7z"], runner.programs());
+        assert!(root.join("output/a/b.txt").is_file());
+    }
+
+    #[test]
+    fn run_failed_decompress_leaves_nothing() {
+        use crate::testing::FakeRunner;
+        let (_tmp, root, file) = root_with_archive("a.zip");
+        // fails halfway through extracting
+        let runner =
+            FakeRunner::new().exits_extracting("7z", 2, &[("a.txt", "a")]);
+        let options = ManageOptions {
+            file: Some(file.clone()),
+            runner: Some(Arc::new(runner)),
+            ..Default::default()
+        };
+
+        assert!(run(&Cfg::default(), Some(&root), options).is_err());
+        assert_eq!(0, fs::read_dir(root.join("output")).unwrap().count());
+        assert!(file.is_file());
+    }
+
+    #[test]
+    fn run_dry_run() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("a.zip");
+        fs::write(&file, "").unwrap();
+
+        let summary = run(
+            &Cfg::default(),
+            Some(root.path()),
+            ManageOptions {
+                file: Some(file.clone()),
+                dry_run: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let root = root.path().canonicalize().unwrap();
+        assert_eq!(Some("zip".to_string()), summary.format);
+        assert_eq!(Some(root.join("output/a")), summary.output_dir);
+        assert_eq!(Some(root.join("archive/a.zip")), summary.archive);
+        assert!(summary.dry_run);
+        assert!(file.is_file());
+        assert!(!root.join("output").exists());
+
+        let to = root.join("somewhere/else");
+        let summary = run(
+            &Cfg::default(),
+            Some(&root),
+            ManageOptions {
+                file: Some(file.clone()),
+                dry_run: true,
+                to: Some(to.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(Some(to.clone()), summary.output_dir);
+        assert!(!to.exists());
+    }
+}
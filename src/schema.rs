@@ -0,0 +1,325 @@
+//! JSON Schema of `cfg.toml` for editors with TOML schema support, like
+//! `chewer --config-schema > cfg.schema.json`
+
+use serde_json::{json, Value};
+
+/// Schema of every config layer. All fields are optional since a layer
+/// only sets what it changes
+pub fn cfg_schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "chewwy config",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "formats": {
+                "type": "object",
+                "additionalProperties": { "$ref": "#/definitions/format" }
+            },
+            "commands": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "manage": { "$ref": "#/definitions/manage" },
+                    "compress": { "$ref": "#/definitions/compress" }
+                }
+            },
+            "options": { "$ref": "#/definitions/options" }
+        },
+        "definitions": {
+            "format": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "extensions": strings(),
+                    "decompress": commands(),
+                    "aliases": strings(),
+                    "merge-commands": enum_ref("merge-strategy"),
+                    "priority": { "type": "integer" },
+                    "compress": commands(),
+                    "canonical-extension": { "type": "string" },
+                    "decompress-stdout": commands(),
+                    "decompress-pipe": commands(),
+                    "list": commands(),
+                    "verify": commands(),
+                    "extends": { "type": "string" },
+                    "strip-components": unsigned(),
+                    "try-next-on-failure": { "type": "boolean" },
+                    "mime-types": strings(),
+                    "needs-password": { "type": "boolean" },
+                    "key-file": { "type": "string" }
+                }
+            },
+            "command": {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["path", "args"],
+                "properties": {
+                    "path": { "type": "string" },
+                    "args": strings(),
+                    "cwd": { "type": "string" },
+                    "env": {
+                        "type": "object",
+                        "additionalProperties": { "type": "string" }
+                    },
+                    "success-codes": {
+                        "type": "array",
+                        "items": { "type": "integer" }
+                    }
+                }
+            },
+            "options": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "quiet": { "type": "boolean" },
+                    "color": enum_ref("color-choice"),
+                    "case-sensitive-extensions": { "type": "boolean" },
+                    "redact": strings(),
+                    "default-command": { "type": "string" }
+                }
+            },
+            "compress": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "format": { "type": "string" },
+                    "source-dir-action": enum_ref("source-dir-action")
+                }
+            },
+            "manage": manage(),
+            "directories": {
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {
+                    "search": { "type": "string" },
+                    "output": { "type": "string" },
+                    "archive": { "type": "string" }
+                }
+            },
+            "merge-strategy": unit_enum(&["replace", "prepend", "append"]),
+            "color-choice": unit_enum(&["auto", "always", "never"]),
+            "source-dir-action": tagged_enum(&["do-nothing", "delete"]),
+            "single-file-output": unit_enum(&["keep", "unwrap"]),
+            "empty-output": unit_enum(&["keep", "remove", "error"]),
+            "sort-key": unit_enum(&["name", "modified-desc", "size-desc"]),
+            "output-file-action":
+                unit_enum(&["decompress-to-output-dir", "to-stdout"]),
+            "compressed-file-action":
+                tagged_enum(&["move-to-archive-dir", "do-nothing"]),
+            "on-exists": unit_enum(&["number", "error"])
+        }
+    })
+}
+
+/// Split out of `cfg_schema` to stay under the `json!` recursion limit
+fn manage() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "smart-decompress-directory": { "type": "boolean" },
+            "smart-decompress-depth": unsigned(),
+            "single-file-output": enum_ref("single-file-output"),
+            "empty-output": enum_ref("empty-output"),
+            "search-file": { "type": "boolean" },
+            "search-ignore": strings(),
+            "search-sort": enum_ref("sort-key"),
+            "search-depth": unsigned(),
+            "search-max-entries": unsigned(),
+            "follow-symlinks": { "type": "boolean" },
+            "output-file-action": enum_ref("output-file-action"),
+            "compressed-file-action":
+                enum_ref("compressed-file-action"),
+            "directories": { "$ref": "#/definitions/directories" },
+            "output-dir-template": { "type": "string" },
+            "create-missing-dirs": { "type": "boolean" },
+            "archive-name-template": { "type": "string" },
+            "archive-on-exists": enum_ref("on-exists"),
+            "post-decompress": strings(),
+            "post-decompress-fatal": { "type": "boolean" },
+            "confine-directories": { "type": "boolean" },
+            "verify-checksum": { "type": "boolean" },
+            "require-checksum": { "type": "boolean" },
+            "space-check": { "type": "boolean" },
+            "space-ratio": { "type": "number", "minimum": 0 },
+            "min-free-space": unsigned(),
+            "write-manifest": { "type": "boolean" }
+        }
+    })
+}
+
+fn strings() -> Value {
+    json!({ "type": "array", "items": { "type": "string" } })
+}
+
+fn commands() -> Value {
+    json!({ "type": "array", "items": { "$ref": "#/definitions/command" } })
+}
+
+fn unsigned() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn enum_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/definitions/{name}") })
+}
+
+fn unit_enum(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+/// The unit `values`, or `move-to-dir` with a `path` written as
+/// `{ move-to-dir = { path = "..." } }`
+fn tagged_enum(values: &[&str]) -> Value {
+    json!({
+        "oneOf": [
+            unit_enum(values),
+            {
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["move-to-dir"],
+                "properties": {
+                    "move-to-dir": {
+                        "type": "object",
+                        "additionalProperties": false,
+                        "required": ["path"],
+                        "properties": { "path": { "type": "string" } }
+                    }
+                }
+            }
+        ]
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cfg;
+    use serde::de::{self, Deserialize, Visitor};
+    use std::collections::BTreeSet;
+
+    /// Records the field or variant names serde knows a type by
+    struct Names(Option<&'static [&'static str]>);
+
+    impl<'de> de::Deserializer<'de> for &mut Names {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(
+            self,
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct or enum"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0 = Some(fields);
+            Err(de::Error::custom("recorded"))
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _: &'static str,
+            variants: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0 = Some(variants);
+            Err(de::Error::custom("recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str
+            string bytes byte_buf option unit unit_struct newtype_struct
+            seq tuple tuple_struct map identifier ignored_any
+        }
+    }
+
+    fn names<'de, T: Deserialize<'de>>() -> BTreeSet<&'static str> {
+        let mut names = Names(None);
+        let _ = T::deserialize(&mut names);
+        names.0.expect("a struct or enum").iter().copied().collect()
+    }
+
+    fn definition<'a>(schema: &'a Value, name: &str) -> &'a Value {
+        &schema["definitions"][name]
+    }
+
+    fn properties(schema: &Value) -> BTreeSet<&str> {
+        schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+
+    fn enum_values(schema: &Value) -> BTreeSet<&str> {
+        let variants = match schema["oneOf"].as_array() {
+            Some(variants) => variants.iter().collect(),
+            None => vec![schema],
+        };
+        let mut values = BTreeSet::new();
+        for variant in variants {
+            match variant["enum"].as_array() {
+                Some(units) => {
+                    values.extend(units.iter().map(|v| v.as_str().unwrap()))
+                }
+                None => values.extend(properties(variant)),
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn cfg_schema_matches_types() {
+        let schema = cfg_schema();
+        // valid JSON once printed
+        let printed = serde_json::to_string_pretty(&schema).unwrap();
+        let schema: Value = serde_json::from_str(&printed).unwrap();
+
+        assert_eq!(names::<cfg::Cfg>(), properties(&schema));
+        assert_eq!(
+            names::<cfg::CommandsCfg>(),
+            properties(&schema["properties"]["commands"])
+        );
+        let structs = [
+            ("format", names::<cfg::Format>()),
+            ("command", names::<cfg::Command>()),
+            ("options", names::<cfg::OptionsCfg>()),
+            ("compress", names::<cfg::CompressCommandCfg>()),
+            ("manage", names::<cfg::ManageCommandCfg>()),
+            ("directories", names::<cfg::Directories>()),
+        ];
+        for (name, fields) in structs {
+            assert_eq!(fields, properties(definition(&schema, name)), "{name}");
+        }
+        let enums = [
+            ("merge-strategy", names::<cfg::MergeStrategy>()),
+            ("color-choice", names::<cfg::ColorChoice>()),
+            ("source-dir-action", names::<cfg::SourceDirAction>()),
+            ("single-file-output", names::<cfg::SingleFileOutput>()),
+            ("empty-output", names::<cfg::EmptyOutput>()),
+            ("sort-key", names::<cfg::SortKey>()),
+            ("output-file-action", names::<cfg::OutputFileAction>()),
+            (
+                "compressed-file-action",
+                names::<cfg::CompressedFileAction>(),
+            ),
+            ("on-exists", names::<cfg::OnExists>()),
+        ];
+        for (name, variants) in enums {
+            assert_eq!(
+                variants,
+                enum_values(definition(&schema, name)),
+                "{name}"
+            );
+        }
+        assert!(printed.contains("\"move-to-archive-dir\""));
+        assert!(printed.contains("\"decompress-to-output-dir\""));
+    }
+}
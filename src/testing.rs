@@ -0,0 +1,122 @@
+//! Fakes for tests running commands without the installed tools
+
+use crate::prelude::*;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::file_archiver::{CommandRunner, RunError, RunOptions};
+
+type Effect = Box<dyn Fn(&[String]) + Send + Sync>;
+
+/// Records the commands it runs and ends them as scripted per program.
+/// Programs without a script are not found
+#[derive(Default)]
+pub struct FakeRunner {
+    scripts: HashMap<String, (i32, Option<Effect>)>,
+    runs: Mutex<Vec<Vec<String>>>,
+}
+
+impl fmt::Debug for FakeRunner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FakeRunner")
+            .field("runs", &self.runs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FakeRunner {
+    pub fn new() -> FakeRunner {
+        FakeRunner::default()
+    }
+
+    /// `program` exits with `code`
+    pub fn exits(mut self, program: &str, code: i32) -> Self {
+        self.scripts.insert(program.to_string(), (code, None));
+        self
+    }
+
+    /// `program` calls `effect` with its args, then exits with `code`
+    pub fn exits_with<E>(mut self, program: &str, code: i32, effect: E) -> Self
+    where
+        E: Fn(&[String]) + Send + Sync + 'static,
+    {
+        self.scripts
+            .insert(program.to_string(), (code, Some(Box::new(effect))));
+        self
+    }
+
+    /// `program`, called like `7z x {FILE} -o{DIR}`, creates `files` as
+    /// name and content in `{DIR}`, then succeeds
+    pub fn extracts(self, program: &str, files: &[(&str, &str)]) -> Self {
+        self.exits_extracting(program, 0, files)
+    }
+
+    /// Like `extracts`, then exits with `code`
+    pub fn exits_extracting(
+        self,
+        program: &str,
+        code: i32,
+        files: &[(&str, &str)],
+    ) -> Self {
+        let files = files
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect::<Vec<_>>();
+        self.exits_with(program, code, move |args| {
+            let dir = args
+                .iter()
+                .find_map(|arg| arg.strip_prefix("-o"))
+                .expect("an -o{DIR} argument");
+            let dir = Path::new(dir);
+            fs::create_dir_all(dir).unwrap();
+            for (name, content) in &files {
+                fs::write(dir.join(name), content).unwrap();
+            }
+        })
+    }
+
+    /// Program and args of every command run, in order
+    pub fn runs(&self) -> Vec<Vec<String>> {
+        self.runs.lock().unwrap().clone()
+    }
+
+    /// Programs of every command run, in order
+    pub fn programs(&self) -> Vec<String> {
+        self.runs().into_iter().map(|run| run[0].clone()).collect()
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(
+        &self,
+        command: &mut process::Command,
+        _options: RunOptions,
+    ) -> Result<process::ExitStatus, RunError> {
+        let program = command.get_program().to_string_lossy().to_string();
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let mut run = vec![program.clone()];
+        run.extend(args.iter().cloned());
+        self.runs.lock().unwrap().push(run);
+        let Some((code, effect)) = self.scripts.get(&program) else {
+            return Err(RunError::Spawn(io::ErrorKind::NotFound.into()));
+        };
+        if let Some(effect) = effect {
+            effect(&args);
+        }
+        Ok(exit_status(*code))
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> process::ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    process::ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> process::ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    process::ExitStatus::from_raw(code as u32)
+}
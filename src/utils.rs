@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use std::time;
 
 #[derive(Debug, Error)]
 pub enum UnnestDirError {
@@ -6,6 +7,8 @@ pub enum UnnestDirError {
     Empty,
     #[error("directory is not nested")]
     NotNested,
+    #[error("\"{}\" appeared while unnesting, the content is left at \"{}\"", target.display(), moved.display())]
+    TargetExists { target: PathBuf, moved: PathBuf },
     #[error("{0}")]
     Io(io::Error),
 }
@@ -31,20 +34,794 @@ pub fn unnest_dir<P: AsRef<Path>>(dir: P) -> Result<(), UnnestDirError> {
         return Err(UnnestDirError::NotNested);
     }
 
-    // HACK: random name so it have the least chance for name conflict
-    // and it being the easiest solution
     let inner_dir_path = entry.path();
-    let random_inner_dir_to_outside_path = inner_dir_path
-        .parent()
-        .expect("parent")
-        .with_file_name("an7v54k42xp2zu4cijb2xg3ipn7bsa");
+    let random_inner_dir_to_outside_path =
+        unused_sibling_path(&outer_dir_path, "unnest");
+    log::debug!(
+        "moving `{}` to `{}`",
+        inner_dir_path.display(),
+        random_inner_dir_to_outside_path.display()
+    );
     fs::rename(&inner_dir_path, &random_inner_dir_to_outside_path)
         .map_err(UnnestDirError::Io)?;
     fs::remove_dir(&outer_dir_path).map_err(UnnestDirError::Io)?;
-    fs::rename(
-        &random_inner_dir_to_outside_path,
-        random_inner_dir_to_outside_path.with_file_name(outer_dir_name),
+    let final_path =
+        random_inner_dir_to_outside_path.with_file_name(outer_dir_name);
+    log::debug!(
+        "moving `{}` to `{}`",
+        random_inner_dir_to_outside_path.display(),
+        final_path.display()
+    );
+    rename_unnested(&random_inner_dir_to_outside_path, &final_path)
+}
+
+/// The last step of `unnest_dir`, renaming would replace an empty
+/// directory that took the name of the removed outer directory
+fn rename_unnested(moved: &Path, target: &Path) -> Result<(), UnnestDirError> {
+    if target.symlink_metadata().is_ok() {
+        return Err(UnnestDirError::TargetExists {
+            target: target.to_path_buf(),
+            moved: moved.to_path_buf(),
+        });
+    }
+    fs::rename(moved, target).map_err(UnnestDirError::Io)
+}
+
+/// `unnest_dir` until `dir` has more than one entry or a file, at most
+/// `max_depth` times. Returns how many levels were collapsed
+pub fn unnest_dir_recursive<P: AsRef<Path>>(
+    dir: P,
+    max_depth: usize,
+) -> Result<usize, UnnestDirError> {
+    let dir = dir.as_ref();
+    let mut levels = 0;
+    while levels < max_depth {
+        match unnest_dir(dir) {
+            Ok(()) => levels += 1,
+            Err(UnnestDirError::Empty | UnnestDirError::NotNested) => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(levels)
+}
+
+/// What a decompressed directory contains
+#[derive(Debug, PartialEq, Eq)]
+pub enum DirLayout {
+    Empty,
+    /// Only this directory, the archive had a wrapper directory
+    SingleDir(PathBuf),
+    /// Only this file
+    SingleFile(PathBuf),
+    /// More than one entry, the archive was flat
+    Flat,
+}
+
+pub fn dir_layout<P: AsRef<Path>>(dir: P) -> io::Result<DirLayout> {
+    let mut entries = fs::read_dir(dir)?;
+    let Some(entry) = entries.next().transpose()? else {
+        return Ok(DirLayout::Empty);
+    };
+    if entries.next().is_some() {
+        return Ok(DirLayout::Flat);
+    }
+    if entry.file_type()?.is_dir() {
+        Ok(DirLayout::SingleDir(entry.path()))
+    } else {
+        Ok(DirLayout::SingleFile(entry.path()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MoveFileError {
+    #[error("{0}")]
+    Rename(io::Error),
+    #[error("cross-device copy failed {0}")]
+    CrossDeviceCopy(io::Error),
+    #[error("copied across devices but cannot remove the source {0}")]
+    CrossDeviceRemoveSource(io::Error),
+}
+
+/// Move a file, falling back to copy then remove when `src` and `dst` are
+/// on different filesystems
+pub fn move_file<S: AsRef<Path>, D: AsRef<Path>>(
+    src: S,
+    dst: D,
+) -> Result<(), MoveFileError> {
+    let (src, dst) = (src.as_ref(), dst.as_ref());
+    match fs::rename(src, dst) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            log::debug!(
+                "`{}` and `{}` are on different filesystems, copying",
+                src.display(),
+                dst.display()
+            );
+            if let Err(e) = fs::copy(src, dst) {
+                let _ = fs::remove_file(dst);
+                return Err(MoveFileError::CrossDeviceCopy(e));
+            }
+            fs::remove_file(src).map_err(MoveFileError::CrossDeviceRemoveSource)
+        }
+        Err(e) => Err(MoveFileError::Rename(e)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateToken<'a> {
+    Text(&'a str),
+    /// Name inside `{...}`
+    Placeholder(&'a str),
+}
+
+/// Split a template into text and `{NAME}` placeholders.
+/// `{{` and `}}` are escapes for literal braces
+pub fn template_tokens(template: &str) -> Vec<TemplateToken<'_>> {
+    let mut tokens = vec![];
+    let mut rest = template;
+    while !rest.is_empty() {
+        if let Some(after) =
+            rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}"))
+        {
+            tokens.push(TemplateToken::Text(&rest[..1]));
+            rest = after;
+            continue;
+        }
+        if let Some(after_open) = rest.strip_prefix('{') {
+            if let Some(end) = after_open.find(['{', '}']) {
+                if after_open[end..].starts_with('}') {
+                    tokens.push(TemplateToken::Placeholder(&after_open[..end]));
+                    rest = &after_open[end + 1..];
+                    continue;
+                }
+            }
+        }
+        // text runs until the next brace, a lone brace is literal
+        let first_len = rest.chars().next().map_or(0, char::len_utf8);
+        let end = rest[first_len..]
+            .find(['{', '}'])
+            .map_or(rest.len(), |i| i + first_len);
+        tokens.push(TemplateToken::Text(&rest[..end]));
+        rest = &rest[end..];
+    }
+    tokens
+}
+
+/// Substitute the placeholders `value` knows, unknown ones are kept as is
+pub fn render_template<'a, F>(template: &str, value: F) -> String
+where
+    F: Fn(&str) -> Option<&'a str>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    for token in template_tokens(template) {
+        match token {
+            TemplateToken::Text(text) => rendered.push_str(text),
+            TemplateToken::Placeholder(name) => match value(name) {
+                Some(value) => rendered.push_str(value),
+                None => {
+                    rendered.push('{');
+                    rendered.push_str(name);
+                    rendered.push('}');
+                }
+            },
+        }
+    }
+    rendered
+}
+
+/// UTC date and time of `secs` since the unix epoch as
+/// `(year, month, day, hour, minute, second)`
+pub fn utc_from_unix_secs(secs: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+    // Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        year,
+        month,
+        day,
+        (secs_of_day / 3600) as u32,
+        (secs_of_day / 60 % 60) as u32,
+        (secs_of_day % 60) as u32,
     )
-    .map_err(UnnestDirError::Io)?;
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseDurationError {
+    #[error("\"{0}\" is not a number followed by s, m, h or d")]
+    Invalid(String),
+    #[error("\"{0}\" is too long")]
+    Overflow(String),
+}
+
+/// Parse a duration like `90s`, `30m`, `24h` or `7d`
+pub fn parse_duration(s: &str) -> Result<time::Duration, ParseDurationError> {
+    let invalid = || ParseDurationError::Invalid(s.to_string());
+    let split = s.len().checked_sub(1).ok_or_else(invalid)?;
+    let (number, unit) = s.split_at_checked(split).ok_or_else(invalid)?;
+    let secs_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let secs = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(secs_per_unit))
+        .ok_or_else(|| ParseDurationError::Overflow(s.to_string()))?;
+    Ok(time::Duration::from_secs(secs))
+}
+
+/// `bytes` in binary units like `1.5 KiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// `path` with the first unused number appended to the file name before
+/// its extensions. `a.tar.gz` -> `a-1.tar.gz`
+pub fn unused_numbered_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    // a leading dot is part of the name
+    let split = file_name
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '.')
+        .map_or(file_name.len(), |(i, _)| i);
+    let (name, extensions) = file_name.split_at(split);
+    (1..)
+        .map(|i| path.with_file_name(format!("{name}-{i}{extensions}")))
+        .find(|candidate| candidate.symlink_metadata().is_err())
+        .expect("unused path")
+}
+
+/// Find a path next to `path` that doesn't exist yet, for use as an
+/// intermediate name
+pub fn unused_sibling_path<P: AsRef<Path>>(path: P, tag: &str) -> PathBuf {
+    let path = path.as_ref();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let pid = process::id();
+    (0..)
+        .map(|i| path.with_file_name(format!(".{file_name}.{tag}-{pid}-{i}")))
+        .find(|candidate| {
+            // `symlink_metadata` so a dangling symlink counts as taken
+            candidate.symlink_metadata().is_err()
+        })
+        .expect("unused path")
+}
+
+/// Where `program` would be run from when searching `path_var`, like
+/// `PATH`. A `program` with a separator is checked as is
+pub fn find_executable(program: &str, path_var: &OsStr) -> Option<PathBuf> {
+    fn is_executable(path: &Path) -> bool {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+        }
+        #[cfg(not(unix))]
+        {
+            metadata.is_file()
+        }
+    }
+    if Path::new(program).components().count() > 1 {
+        let program = PathBuf::from(program);
+        return is_executable(&program).then_some(program);
+    }
+    env::split_paths(path_var)
+        .map(|dir| dir.join(program))
+        .find(|candidate| {
+            is_executable(candidate)
+                || cfg!(windows)
+                    && is_executable(&candidate.with_extension("exe"))
+        })
+}
+
+#[derive(Debug, Error)]
+pub enum ExpandGlobsError {
+    #[error("invalid pattern \"{pattern}\": {error}")]
+    Pattern {
+        pattern: String,
+        error: glob::PatternError,
+    },
+    #[error("pattern \"{0}\" matches nothing")]
+    NoMatch(String),
+    #[error("{0}")]
+    Glob(glob::GlobError),
+}
+
+/// Replace every argument containing `*`, `?` or `[` with the sorted paths
+/// it matches. Other arguments are kept as is
+pub fn expand_globs(
+    args: Vec<PathBuf>,
+) -> Result<Vec<PathBuf>, ExpandGlobsError> {
+    let mut expanded = vec![];
+    for arg in args {
+        let pattern = arg.to_string_lossy();
+        if !pattern.contains(['*', '?', '[']) {
+            expanded.push(arg);
+            continue;
+        }
+        let paths = glob::glob(&pattern).map_err(|error| {
+            ExpandGlobsError::Pattern {
+                pattern: pattern.to_string(),
+                error,
+            }
+        })?;
+        let len = expanded.len();
+        for path in paths {
+            expanded.push(path.map_err(ExpandGlobsError::Glob)?);
+        }
+        if expanded.len() == len {
+            return Err(ExpandGlobsError::NoMatch(pattern.to_string()));
+        }
+    }
+    Ok(expanded)
+}
+
+/// Bytes available to unprivileged users on the filesystem of `path`, or
+/// of its closest existing ancestor when it doesn't exist yet
+#[cfg(unix)]
+pub fn available_space<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let path = path.as_ref();
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("."));
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `c_path` is nul terminated and `stat` is only read after
+    // `statvfs` succeeded filling it
+    let stat = unsafe {
+        if libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    // the field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_space<P: AsRef<Path>>(_path: P) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "checking available space is only supported on unix",
+    ))
+}
+
+/// Informational messages on stdout, silenced when `quiet` so only the
+/// requested output and errors are printed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Messages {
+    pub quiet: bool,
+    /// Print on stderr, leaving stdout to the output read by other programs
+    pub stderr: bool,
+}
+
+impl Messages {
+    pub fn new(quiet: bool) -> Messages {
+        Messages {
+            quiet,
+            stderr: false,
+        }
+    }
+
+    pub fn stderr(mut self, stderr: bool) -> Self {
+        self.stderr = stderr;
+        self
+    }
+
+    pub fn say<M: fmt::Display>(&self, message: M) {
+        if !self.quiet {
+            self.print(format_args!("{message}\n"));
+        }
+    }
+
+    /// Print even when quiet, for what is needed to go on like a prompt
+    pub fn print<M: fmt::Display>(&self, message: M) {
+        if self.stderr {
+            eprint!("{message}");
+        } else {
+            print!("{message}");
+        }
+    }
+}
+
+/// Resolve `.` and `..` components without touching the filesystem.
+/// `..` at the root stays at the root, leading `..` of a relative path are
+/// kept
+pub fn normalize_lexically<P: AsRef<Path>>(path: P) -> PathBuf {
+    use std::path::Component;
+    let mut normalized = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                Some(Component::ParentDir | Component::CurDir) | None => {
+                    normalized.push("..")
+                }
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Md5,
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: &'static [ChecksumAlgorithm] =
+        &[ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Md5];
+
+    /// Extension of the sidecar file holding the checksum of `<file>`,
+    /// `<file>.sha256` for example
+    pub fn sidecar_extension(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Md5 => "md5",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum VerifyChecksumError {
+    #[error("checksum mismatch, expected {expected} but found {actual}")]
+    Mismatch { expected: String, actual: String },
+    #[error("{0}")]
+    Io(io::Error),
+}
+
+/// Lowercase hex digest of the file content
+pub fn file_checksum<P: AsRef<Path>>(
+    file: P,
+    algorithm: ChecksumAlgorithm,
+) -> io::Result<String> {
+    use io::Read;
+    use md5::Digest;
+    fn hash<D: Digest>(
+        mut file: fs::File,
+        mut hasher: D,
+    ) -> io::Result<String> {
+        let mut buf = [0; 8192];
+        loop {
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            hasher.update(&buf[..len]);
+        }
+        let digest = hasher.finalize();
+        Ok(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+    }
+    let file = fs::File::open(file)?;
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => hash(file, sha2::Sha256::new()),
+        ChecksumAlgorithm::Md5 => hash(file, md5::Md5::new()),
+    }
+}
+
+/// `expected` is a hex digest in any case
+pub fn verify_checksum<P: AsRef<Path>>(
+    file: P,
+    expected: &str,
+    algorithm: ChecksumAlgorithm,
+) -> Result<(), VerifyChecksumError> {
+    let actual =
+        file_checksum(file, algorithm).map_err(VerifyChecksumError::Io)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(VerifyChecksumError::Mismatch {
+            expected: expected.to_string(),
+            actual,
+        });
+    }
     Ok(())
 }
+
+/// The checksum in a sidecar file, in the `sha256sum` output format or
+/// just the digest
+pub fn read_sidecar_checksum<P: AsRef<Path>>(sidecar: P) -> io::Result<String> {
+    let content = fs::read_to_string(sidecar)?;
+    content
+        .split_whitespace()
+        .next()
+        .map(|checksum| checksum.to_string())
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "sidecar is empty")
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unnest_dir_recursive_levels() {
+        let tmp = tempfile::tempdir().unwrap();
+        for nesting in ["", "a", "a/b", "a/b/c"] {
+            let outer = tmp.path().join("outer");
+            let inner = outer.join(nesting);
+            fs::create_dir_all(&inner).unwrap();
+            fs::write(inner.join("1.txt"), "").unwrap();
+            fs::write(inner.join("2.txt"), "").unwrap();
+            let levels = nesting.split('/').filter(|s| !s.is_empty()).count();
+
+            assert_eq!(levels, unnest_dir_recursive(&outer, 3).unwrap());
+            assert!(outer.join("1.txt").is_file(), "{nesting}");
+            assert!(outer.join("2.txt").is_file(), "{nesting}");
+            fs::remove_dir_all(&outer).unwrap();
+        }
+
+        let outer = tmp.path().join("outer");
+        fs::create_dir_all(outer.join("a/b/c")).unwrap();
+        assert_eq!(2, unnest_dir_recursive(&outer, 2).unwrap());
+        assert!(outer.join("c").is_dir());
+    }
+
+    #[test]
+    fn dir_layout_cases() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        assert_eq!(DirLayout::Empty, dir_layout(&dir).unwrap());
+        fs::write(dir.join("a.txt"), "").unwrap();
+        assert_eq!(
+            DirLayout::SingleFile(dir.join("a.txt")),
+            dir_layout(&dir).unwrap()
+        );
+        fs::write(dir.join("b.txt"), "").unwrap();
+        assert_eq!(DirLayout::Flat, dir_layout(&dir).unwrap());
+
+        let wrapped = tmp.path().join("wrapped");
+        fs::create_dir_all(wrapped.join("inner")).unwrap();
+        assert_eq!(
+            DirLayout::SingleDir(wrapped.join("inner")),
+            dir_layout(&wrapped).unwrap()
+        );
+    }
+
+    #[test]
+    fn unnest_dir_with_old_temp_name_present() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outer = tmp.path().join("outer");
+        fs::create_dir_all(outer.join("inner")).unwrap();
+        fs::write(outer.join("inner/file"), "").unwrap();
+        let old_temp = tmp.path().join("an7v54k42xp2zu4cijb2xg3ipn7bsa");
+        fs::create_dir(&old_temp).unwrap();
+        fs::write(old_temp.join("keep"), "").unwrap();
+
+        unnest_dir(&outer).unwrap();
+
+        assert!(outer.join("file").is_file());
+        assert!(!outer.join("inner").exists());
+        assert!(old_temp.join("keep").is_file());
+    }
+
+    #[test]
+    fn rename_unnested_keeps_conflicting_sibling() {
+        let tmp = tempfile::tempdir().unwrap();
+        let moved = tmp.path().join("moved");
+        fs::create_dir(&moved).unwrap();
+        fs::write(moved.join("file"), "").unwrap();
+        let target = tmp.path().join("outer");
+        fs::create_dir(&target).unwrap();
+
+        assert!(matches!(
+            rename_unnested(&moved, &target),
+            Err(UnnestDirError::TargetExists { .. })
+        ));
+        assert!(moved.join("file").is_file());
+        assert!(target.is_dir());
+        assert!(!target.join("file").exists());
+
+        fs::remove_dir(&target).unwrap();
+        rename_unnested(&moved, &target).unwrap();
+        assert!(target.join("file").is_file());
+    }
+
+    #[test]
+    fn move_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let src = tmp.path().join("src");
+        let dst = tmp.path().join("dst");
+        fs::write(&src, "content").unwrap();
+
+        super::move_file(&src, &dst).unwrap();
+        assert!(!src.exists());
+        assert_eq!("content", fs::read_to_string(&dst).unwrap());
+
+        let missing_dir_dst = tmp.path().join("missing/dst");
+        assert!(matches!(
+            super::move_file(&dst, missing_dir_dst),
+            Err(MoveFileError::Rename(_))
+        ));
+        assert!(dst.exists());
+    }
+
+    #[test]
+    fn render_template() {
+        let value = |name: &str| match name {
+            "FILE" => Some("a.zip"),
+            "DIR" => Some("out"),
+            _ => None,
+        };
+        let render = |template| super::render_template(template, value);
+        assert_eq!("-oout", render("-o{DIR}"));
+        assert_eq!("a.zipout", render("{FILE}{DIR}"));
+        assert_eq!("{FILE}", render("{{FILE}}"));
+        assert_eq!("{a.zip}", render("{{{FILE}}}"));
+        assert_eq!("{OUTPUT} a.zip", render("{OUTPUT} {FILE}"));
+        assert_eq!("{ out } {", render("{ {DIR} } {"));
+        assert_eq!("}", render("}"));
+        assert_eq!("é/out", render("é/{DIR}"));
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        let secs = |secs| Ok(time::Duration::from_secs(secs));
+        assert_eq!(secs(90), parse_duration("90s"));
+        assert_eq!(secs(30 * 60), parse_duration("30m"));
+        assert_eq!(secs(24 * 3600), parse_duration("24h"));
+        assert_eq!(secs(7 * 86400), parse_duration("7d"));
+        for invalid in ["", "d", "7", "7w", "-1d", "1.5h", " 1h", "1ä"] {
+            assert_eq!(
+                Err(ParseDurationError::Invalid(invalid.to_string())),
+                parse_duration(invalid)
+            );
+        }
+        assert!(matches!(
+            parse_duration("99999999999999999999d"),
+            Err(ParseDurationError::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn human_size_units() {
+        assert_eq!("0 B", human_size(0));
+        assert_eq!("1023 B", human_size(1023));
+        assert_eq!("1.0 KiB", human_size(1024));
+        assert_eq!("1.5 KiB", human_size(1536));
+        assert_eq!("2.0 MiB", human_size(2 * 1024 * 1024));
+        assert_eq!("1024.0 PiB", human_size(1 << 60));
+    }
+
+    #[test]
+    fn utc_from_unix_secs_dates() {
+        assert_eq!((1970, 1, 1, 0, 0, 0), utc_from_unix_secs(0));
+        assert_eq!((2000, 2, 29, 23, 59, 59), utc_from_unix_secs(951868799));
+        assert_eq!((2024, 12, 31, 12, 30, 5), utc_from_unix_secs(1735648205));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn available_space_of_missing_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let existing = available_space(tmp.path()).unwrap();
+        assert!(existing > 0);
+        assert!(available_space(tmp.path().join("missing/dir")).unwrap() > 0);
+    }
+
+    #[test]
+    fn unused_numbered_path_skips_existing() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::write(tmp.path().join("a-1.tar.gz"), "").unwrap();
+        assert_eq!(
+            tmp.path().join("a-2.tar.gz"),
+            unused_numbered_path(tmp.path().join("a.tar.gz"))
+        );
+        assert_eq!(
+            tmp.path().join(".hidden-1"),
+            unused_numbered_path(tmp.path().join(".hidden"))
+        );
+    }
+
+    #[test]
+    fn unused_sibling_path_skips_existing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("dir");
+        let first = unused_sibling_path(&path, "tag");
+        fs::create_dir(&first).unwrap();
+        let second = unused_sibling_path(&path, "tag");
+        assert_ne!(first, second);
+        assert!(!second.exists());
+        assert_eq!(tmp.path(), second.parent().unwrap());
+    }
+
+    #[test]
+    fn verify_checksum_sidecar() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("a.zip");
+        fs::write(&file, "hello\n").unwrap();
+        let sidecar = tmp.path().join("a.zip.sha256");
+        fs::write(
+            &sidecar,
+            "5891B5B522D5DF086D0FF0B110FBD9D21BB4FC7163AF34D08286A2E846F6BE03  a.zip\n",
+        )
+        .unwrap();
+
+        let expected = read_sidecar_checksum(&sidecar).unwrap();
+        verify_checksum(&file, &expected, ChecksumAlgorithm::Sha256).unwrap();
+        verify_checksum(
+            &file,
+            "b1946ac92492d2347c6235b4d2611184",
+            ChecksumAlgorithm::Md5,
+        )
+        .unwrap();
+        assert!(matches!(
+            verify_checksum(&file, "00", ChecksumAlgorithm::Md5),
+            Err(VerifyChecksumError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn normalize_lexically_paths() {
+        for (path, expected) in [
+            ("/a/./b/../c", "/a/c"),
+            ("/../a", "/a"),
+            ("a/../../b", "../b"),
+            ("./a/", "a"),
+            ("a/..", ""),
+        ] {
+            assert_eq!(PathBuf::from(expected), normalize_lexically(path));
+        }
+    }
+
+    #[test]
+    fn expand_globs_args() {
+        let tmp = tempfile::tempdir().unwrap();
+        for name in ["b.zip", "a.zip", "c.rar"] {
+            fs::write(tmp.path().join(name), "").unwrap();
+        }
+        let pattern = tmp.path().join("*.zip");
+        let plain = PathBuf::from("plain.zip");
+        assert_eq!(
+            vec![
+                tmp.path().join("a.zip"),
+                tmp.path().join("b.zip"),
+                plain.clone()
+            ],
+            expand_globs(vec![pattern, plain]).unwrap()
+        );
+        assert!(matches!(
+            expand_globs(vec![tmp.path().join("*.7z")]),
+            Err(ExpandGlobsError::NoMatch(_))
+        ));
+    }
+}
@@ -0,0 +1,253 @@
+//! `manage --watch` polls the search directory and manages every file of a
+//! known format once its size stops changing, so a download in progress
+//! isn't picked up. Files already there are managed like new ones
+
+use crate::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    thread, time,
+};
+
+use crate::{
+    cfg::{Cfg, StructMerge},
+    file_archiver::FileArchiver,
+    interrupt,
+    manage::{self, ManageOptions, ManageSummary, SearchIgnore, Selection},
+};
+
+#[derive(Debug, Error)]
+#[error("watch error")]
+pub struct WatchError;
+
+/// Time between two listings of the search directory
+pub const POLL_INTERVAL: time::Duration = time::Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    /// Options every file is managed with, its `file` is ignored
+    pub manage: ManageOptions,
+    pub interval: time::Duration,
+    /// Stop after listing the search directory this many times, watch
+    /// until interrupted when `None`
+    pub polls: Option<usize>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            manage: ManageOptions::default(),
+            interval: POLL_INTERVAL,
+            polls: None,
+        }
+    }
+}
+
+/// Size and modification time of a listed file
+pub type FileState = (u64, Option<time::SystemTime>);
+
+/// Files seen while polling. One is ready when it has the same state on
+/// two polls in a row, and then never ready again until it's gone
+#[derive(Debug, Default)]
+pub struct Pending {
+    seen: HashMap<PathBuf, FileState>,
+    done: HashSet<PathBuf>,
+}
+
+impl Pending {
+    /// Record a listing of the directory, returns the files that became
+    /// ready in the order of `files`
+    pub fn poll(&mut self, files: Vec<(PathBuf, FileState)>) -> Vec<PathBuf> {
+        let listed = files
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<HashSet<_>>();
+        self.seen.retain(|path, _| listed.contains(path));
+        self.done.retain(|path| listed.contains(path));
+        let mut ready = vec![];
+        for (path, state) in files {
+            if self.done.contains(&path) {
+                continue;
+            }
+            if self.seen.get(&path) == Some(&state) {
+                self.seen.remove(&path);
+                self.done.insert(path.clone());
+                ready.push(path);
+            } else {
+                self.seen.insert(path, state);
+            }
+        }
+        ready
+    }
+}
+
+/// Manage the files appearing in the search directory until interrupted.
+/// A file that fails is logged and left alone, it doesn't stop watching
+pub fn run(
+    cfg: &Cfg,
+    chewwy_root: Option<&Path>,
+    options: WatchOptions,
+) -> StackResult<Vec<ManageSummary>, WatchError> {
+    let Some(chewwy_root) = chewwy_root else {
+        return Err(crate::NoChewwyRoot).change_context(WatchError);
+    };
+    let manage_cfg = cfg.commands.c().manage.c();
+    let mut directories = options.manage.directories.clone();
+    directories.struct_merge(manage_cfg.directories.c());
+    let directories = directories.to_absolute(chewwy_root);
+    let Some(search_dir) = directories.search.c() else {
+        return Err(WatchError).attach_printable(
+            "nothing to watch, `commands.manage.directories.search` is not \
+             set, set it or pass `--search-dir`",
+        );
+    };
+    let search_ignore = SearchIgnore::new(manage_cfg.search_ignore.c())
+        .change_context(WatchError)
+        .attach_printable(
+            "invalid pattern in `commands.manage.search-ignore`",
+        )?;
+    let options_cfg = cfg.options.c();
+    let file_archiver = FileArchiver::new(cfg.formats.c())
+        .case_sensitive_extensions(*options_cfg.case_sensitive_extensions.c());
+    let messages = crate::utils::Messages::new(*options_cfg.quiet.c())
+        .stderr(options.manage.messages_to_stderr);
+    messages.say(format!("Watching `{}`", search_dir.display()));
+
+    let mut pending = Pending::default();
+    let mut managed = vec![];
+    let mut polls = 0;
+    while !interrupt::interrupted() {
+        if options.polls.is_some_and(|max| polls >= max) {
+            break;
+        }
+        if polls > 0 {
+            thread::sleep(options.interval);
+        }
+        polls += 1;
+        let files = match list_files(search_dir, &search_ignore) {
+            Ok(files) => files,
+            Err(e) => {
+                log::warn!("cannot read `{}`: {e}", search_dir.display());
+                continue;
+            }
+        };
+        let files = files
+            .into_iter()
+            .filter(|(path, _)| {
+                matches!(file_archiver.detect_format(path), Ok(Some(_)))
+            })
+            .collect();
+        for file in pending.poll(files) {
+            let manage_options = ManageOptions {
+                file: Some(file.clone()),
+                selection: Selection::Single,
+                ..options.manage.clone()
+            };
+            match manage::run(cfg, Some(chewwy_root), manage_options) {
+                Ok(summary) => managed.push(summary),
+                Err(report) => {
+                    log::error!(
+                        "cannot manage `{}`: {report:?}",
+                        file.display()
+                    )
+                }
+            }
+            if interrupt::interrupted() {
+                break;
+            }
+        }
+    }
+    Ok(managed)
+}
+
+/// The files directly in `dir` not left out by `search_ignore`
+fn list_files(
+    dir: &Path,
+    search_ignore: &SearchIgnore,
+) -> io::Result<Vec<(PathBuf, FileState)>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        // unreadable entries are looked at again next poll
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if search_ignore.is_ignored(&entry.file_name()) {
+            continue;
+        }
+        // or gone since listed
+        let Ok(metadata) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        if metadata.is_file() {
+            let state = (metadata.len(), metadata.modified().ok());
+            files.push((entry.path(), state));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pending_waits_for_stable_files() {
+        let a = PathBuf::from("a.zip");
+        let b = PathBuf::from("b.zip");
+        let state = |size| (size, None);
+        let mut pending = Pending::default();
+
+        assert!(pending.poll(vec![(a.clone(), state(1))]).is_empty());
+        // still growing
+        assert!(pending.poll(vec![(a.clone(), state(2))]).is_empty());
+        assert_eq!(
+            vec![a.clone()],
+            pending.poll(vec![(a.clone(), state(2)), (b.clone(), state(1))])
+        );
+        // a is done, b is now stable
+        assert_eq!(
+            vec![b.clone()],
+            pending.poll(vec![(a.clone(), state(2)), (b.clone(), state(1))])
+        );
+        assert!(pending.poll(vec![(a.clone(), state(2))]).is_empty());
+
+        // a file dropped again after being moved away is managed again
+        assert!(pending.poll(vec![]).is_empty());
+        assert!(pending.poll(vec![(a.clone(), state(2))]).is_empty());
+        assert_eq!(vec![a.clone()], pending.poll(vec![(a, state(2))]));
+    }
+
+    #[test]
+    fn run_manages_new_files_and_keeps_going() {
+        use crate::testing::FakeRunner;
+        use std::sync::Arc;
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path().canonicalize().unwrap();
+        let search = root.join("search");
+        fs::create_dir(&search).unwrap();
+        fs::write(search.join("a.zip"), "").unwrap();
+        fs::write(search.join("bad.gz"), "").unwrap();
+        fs::write(search.join("notes.txt"), "").unwrap();
+        fs::write(search.join("b.zip.part"), "").unwrap();
+        let runner =
+            Arc::new(FakeRunner::new().extracts("7z", &[("a.txt", "a")]));
+        let options = WatchOptions {
+            manage: ManageOptions {
+                runner: Some(runner.clone()),
+                ..Default::default()
+            },
+            interval: time::Duration::ZERO,
+            polls: Some(3),
+        };
+
+        let managed = run(&Cfg::default(), Some(&root), options).unwrap();
+        assert_eq!(1, managed.len());
+        assert!(root.join("output/a/a.txt").is_file());
+        assert!(root.join("archive/a.zip").is_file());
+        // the failed gz, with none of its commands, is left and not retried while it doesn't change
+        assert!(search.join("bad.gz").is_file());
+        assert_eq!(1, runner.programs().iter().filter(|p| *p == "7z").count());
+        assert!(search.join("notes.txt").is_file());
+        assert!(search.join("b.zip.part").is_file());
+    }
+}
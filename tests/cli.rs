@@ -0,0 +1,42 @@
+use std::{fs, process};
+
+#[cfg(unix)]
+#[test]
+fn json_manage_prints_only_the_summary() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path().canonicalize().unwrap();
+    fs::create_dir(root.join(".chewwy")).unwrap();
+    // a decompressor as chatty as unzip, with a nested directory to unnest
+    fs::write(
+        root.join(".chewwy/cfg.toml"),
+        r#"
+        [formats.noisy]
+        extensions = ["noisy"]
+        [[formats.noisy.decompress]]
+        path = "sh"
+        args = [
+            "-c",
+            'echo "extracting: a.txt"; mkdir -p "$0/a"; echo a > "$0/a/a.txt"',
+            "{DIR}",
+        ]
+        "#,
+    )
+    .unwrap();
+    fs::write(root.join("a.noisy"), "").unwrap();
+
+    let output = process::Command::new(env!("CARGO_BIN_EXE_chewer"))
+        .args(["--json", "manage", "a.noisy"])
+        .current_dir(&root)
+        .env("HOME", &root)
+        .env("XDG_CONFIG_HOME", root.join("config"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("extracting: a.txt"), "{stderr}");
+    let summary: serde_json::Value = serde_json::from_str(&stdout)
+        .unwrap_or_else(|e| panic!("{e} in stdout {stdout:?}"));
+    assert_eq!("noisy", summary["format"], "{summary}");
+    assert!(root.join("output/a/a.txt").is_file());
+}